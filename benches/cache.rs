@@ -0,0 +1,232 @@
+//! Benchmarks of the hot paths of `Cache`, single-threaded and under contention.
+//!
+//! Run with `cargo bench`. To catch regressions, save a baseline before a change
+//! with `cargo bench -- --save-baseline before`, and compare with it afterwards with
+//! `cargo bench -- --baseline before`.
+
+use std::{
+    hint::black_box,
+    sync::Barrier,
+    thread,
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use fastcache::{Cache, LocalCache, MockClock};
+
+const CAPACITY: usize = 10_000;
+const KEYS: usize = 100_000;
+const TTL: Duration = Duration::from_secs(60);
+
+/// A xorshift generator, good enough to pick keys without a dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Keys following a Zipfian distribution, where key `k` is drawn with a probability
+/// proportional to `1 / (k + 1)^s`, like the popularity of real-world items.
+struct Zipf {
+    cdf: Vec<f64>,
+}
+
+impl Zipf {
+    fn new(n: usize, s: f64) -> Self {
+        let mut total = 0.0;
+        let mut cdf: Vec<f64> = (0..n)
+            .map(|k| {
+                total += 1.0 / ((k + 1) as f64).powf(s);
+                total
+            })
+            .collect();
+        cdf.iter_mut().for_each(|p| *p /= total);
+        Self { cdf }
+    }
+
+    fn sample(&self, rng: &mut Rng) -> u64 {
+        let p = rng.next_f64();
+        self.cdf.partition_point(|&c| c < p) as u64
+    }
+
+    /// Pre-draw keys, so sampling isn't part of the measurement.
+    fn keys(&self, seed: u64, len: usize) -> Vec<u64> {
+        let mut rng = Rng::new(seed);
+        (0..len).map(|_| self.sample(&mut rng)).collect()
+    }
+}
+
+fn filled_cache() -> Cache<u64, u64> {
+    let cache = Cache::new(CAPACITY, TTL);
+    for k in 0..CAPACITY as u64 {
+        cache.insert(k, k);
+    }
+    cache
+}
+
+fn single_thread(c: &mut Criterion) {
+    let mut group = c.benchmark_group("single_thread");
+    group.throughput(Throughput::Elements(1));
+
+    let cache = filled_cache();
+    let mut k = 0;
+    group.bench_function("get_hit", |b| {
+        b.iter(|| {
+            k = (k + 1) % CAPACITY as u64;
+            black_box(cache.get(&k))
+        })
+    });
+    group.bench_function("get_miss", |b| {
+        b.iter(|| {
+            k += 1;
+            black_box(cache.get(&(u64::MAX - k)))
+        })
+    });
+    group.bench_function("insert", |b| {
+        b.iter(|| {
+            k += 1;
+            cache.insert(k, k)
+        })
+    });
+
+    // The same lookups and inserts without the concurrency machinery.
+    let local = LocalCache::new(CAPACITY, TTL);
+    for k in 0..CAPACITY as u64 {
+        local.insert(k, k);
+    }
+    group.bench_function("local_get_hit", |b| {
+        b.iter(|| {
+            k = (k + 1) % CAPACITY as u64;
+            black_box(local.get(&k))
+        })
+    });
+    group.bench_function("local_insert", |b| {
+        b.iter(|| {
+            k += 1;
+            local.insert(k, k)
+        })
+    });
+
+    let keys = Zipf::new(KEYS, 1.0).keys(0, 1 << 16);
+    let cache = filled_cache();
+    let mut i = 0;
+    group.bench_function("zipf_read_90", |b| {
+        b.iter(|| {
+            i = (i + 1) % keys.len();
+            let key = keys[i];
+            if i % 10 == 0 {
+                cache.insert(key, key);
+            } else {
+                black_box(cache.get(&key));
+            }
+        })
+    });
+    group.finish();
+}
+
+/// Run `ops` operations of a mixed workload spread over `threads` threads, with one
+/// write every `write_every` operations, and return how long it took.
+fn run_mixed(
+    cache: &Cache<u64, u64>,
+    threads: usize,
+    ops: u64,
+    write_every: u64,
+    keys: &[u64],
+) -> Duration {
+    let barrier = Barrier::new(threads + 1);
+    let per_thread = ops.div_ceil(threads as u64);
+    thread::scope(|s| {
+        for t in 0..threads {
+            let barrier = &barrier;
+            s.spawn(move || {
+                // Start each thread at another place in the keys.
+                let mut i = t * keys.len() / threads;
+                barrier.wait();
+                for op in 0..per_thread {
+                    i = (i + 1) % keys.len();
+                    let key = keys[i];
+                    if op % write_every == 0 {
+                        cache.insert(key, key);
+                    } else {
+                        black_box(cache.get(&key));
+                    }
+                }
+                barrier.wait();
+            });
+        }
+        barrier.wait();
+        let start = Instant::now();
+        barrier.wait();
+        start.elapsed()
+    })
+}
+
+fn concurrent(c: &mut Criterion) {
+    let keys = Zipf::new(KEYS, 1.0).keys(1, 1 << 16);
+    for (name, write_every) in [("read_90", 10), ("read_50", 2)] {
+        let mut group = c.benchmark_group(format!("concurrent_{name}"));
+        group.throughput(Throughput::Elements(1));
+        for threads in [1, 8, 32, 64] {
+            let cache = filled_cache();
+            group.bench_with_input(
+                BenchmarkId::from_parameter(threads),
+                &threads,
+                |b, &threads| {
+                    b.iter_custom(|ops| run_mixed(&cache, threads, ops, write_every, &keys))
+                },
+            );
+        }
+        group.finish();
+    }
+}
+
+/// Reads where the given percentage of the entries are expired but not removed yet,
+/// which return stale hits.
+fn stale_hits(c: &mut Criterion) {
+    let mut group = c.benchmark_group("stale_hits");
+    group.throughput(Throughput::Elements(1));
+    for stale in [0, 50, 100] {
+        let clock = MockClock::new();
+        let cache = Cache::builder()
+            .capacity(CAPACITY)
+            .ttl(TTL)
+            .clock(clock.clone())
+            .build();
+        // Insert a live entry first, so that the expired ones are stuck behind it and
+        // can't be swept.
+        cache.insert(u64::MAX, 0);
+        let stale_len = (CAPACITY as u64 - 1) * stale / 100;
+        for k in stale_len..CAPACITY as u64 - 1 {
+            cache.insert(k, k);
+        }
+        for k in 0..stale_len {
+            cache.insert_with_ttl(k, k, Duration::from_secs(1));
+        }
+        clock.advance(Duration::from_secs(2));
+        let mut k = 0;
+        group.bench_with_input(BenchmarkId::from_parameter(stale), &stale, |b, _| {
+            b.iter(|| {
+                k = (k + 1) % (CAPACITY as u64 - 1);
+                black_box(cache.get(&k))
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, single_thread, concurrent, stale_hits);
+criterion_main!(benches);
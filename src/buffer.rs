@@ -0,0 +1,85 @@
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, PoisonError,
+    },
+    thread,
+};
+
+use crossbeam_utils::CachePadded;
+
+/// Hands out the stripe of each thread, round robin.
+static NEXT_STRIPE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static STRIPE: usize = NEXT_STRIPE.fetch_add(1, Ordering::Relaxed);
+}
+
+struct Stripe<T> {
+    items: Mutex<Vec<T>>,
+    // The length of `items`, readable without the lock.
+    len: AtomicUsize,
+}
+
+/// A buffer of writes, striped by thread so that threads writing at the same time
+/// rarely touch the same stripe, and flushed in batches.
+pub(crate) struct WriteBuffer<T> {
+    stripes: Box<[CachePadded<Stripe<T>>]>,
+    batch: usize,
+}
+
+impl<T> WriteBuffer<T> {
+    /// Create a buffer flushing batches of `batch` items, with a stripe per CPU,
+    /// rounded up to a power of two.
+    pub(crate) fn new(batch: usize) -> Self {
+        let stripes = thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .next_power_of_two();
+        Self {
+            stripes: (0..stripes)
+                .map(|_| {
+                    CachePadded::new(Stripe {
+                        items: Mutex::new(Vec::with_capacity(batch)),
+                        len: AtomicUsize::new(0),
+                    })
+                })
+                .collect(),
+            batch,
+        }
+    }
+
+    /// Get the number of buffered items.
+    pub(crate) fn len(&self) -> usize {
+        self.stripes
+            .iter()
+            .map(|s| s.len.load(Ordering::Acquire))
+            .sum()
+    }
+
+    /// Buffer an item in the stripe of the current thread, and return the batch to
+    /// flush if the stripe is full.
+    pub(crate) fn push(&self, item: T) -> Option<Vec<T>> {
+        let i = STRIPE.with(|i| *i) & (self.stripes.len() - 1);
+        let stripe = &self.stripes[i];
+        let mut items = stripe.items.lock().unwrap_or_else(PoisonError::into_inner);
+        items.push(item);
+        if items.len() < self.batch {
+            stripe.len.store(items.len(), Ordering::Release);
+            return None;
+        }
+        stripe.len.store(0, Ordering::Release);
+        Some(mem::replace(&mut *items, Vec::with_capacity(self.batch)))
+    }
+
+    /// Take all the buffered items.
+    pub(crate) fn drain(&self) -> Vec<T> {
+        let mut drained = Vec::new();
+        for stripe in self.stripes.iter() {
+            let mut items = stripe.items.lock().unwrap_or_else(PoisonError::into_inner);
+            drained.append(&mut items);
+            stripe.len.store(0, Ordering::Release);
+        }
+        drained
+    }
+}
@@ -0,0 +1,705 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    mem,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crossbeam_utils::{atomic::AtomicCell, CachePadded};
+use dashmap::{DashMap, DashSet};
+
+use crate::{
+    clock::Clock, expiry, listener::EvictionListener, refresh, ringbuf::Ringbuf,
+    sketch::FrequencySketch, stats::Counters, trace::Tracer, AdmissionPolicy, BackgroundTask,
+    Cache, CacheConfigError, EvictionPolicy, Expiry, Pool, RemovalCause, ShardedCache, Slot,
+    WriteBuffer,
+};
+
+/// The number of entries the map of a cache is allocated for up front. Larger caches
+/// grow their map as they fill up, so that their memory follows their occupancy.
+pub(crate) const MAX_PREALLOCATED: usize = 1 << 16;
+
+/// A function computing the weight of an entry.
+pub(crate) type Weigher<K, V> = Arc<dyn Fn(&K, &V) -> u32 + Send + Sync>;
+
+/// A function computing the heap memory used by an entry.
+pub(crate) type MemSizer<K, V> = Arc<dyn Fn(&K, &V) -> usize + Send + Sync>;
+
+/// A function reloading the value of a key, for refresh-ahead.
+pub(crate) type Refresher<K, V> = Arc<dyn Fn(&K) -> V + Send + Sync>;
+
+/// A function running a task in the background, or giving it back if it can't.
+pub(crate) type Spawner = Arc<dyn Fn(BackgroundTask) -> Result<(), BackgroundTask> + Send + Sync>;
+
+/// The jitter of the TTL, which may be relative to the TTL.
+#[derive(Clone, Copy)]
+enum TtlJitter {
+    Duration(Duration),
+    Fraction(f64),
+}
+
+/// A builder for [`Cache`].
+///
+/// Both the capacity and the time-to-live (TTL) must be set before calling
+/// [`build`](Self::build).
+///
+/// A capacity of zero or a zero TTL builds a disabled cache, which is useful to
+/// turn caching off through configuration: nothing is ever stored, so every lookup
+/// misses and [`Cache::get_or_insert_with`] always computes the value. Inserted
+/// values are dropped right away, and reported to the eviction listener with
+/// [`RemovalCause::Size`] as if they weren't admitted.
+pub struct CacheBuilder<K, V, S = ahash::RandomState> {
+    capacity: Option<usize>,
+    ttl: Option<Duration>,
+    ttl_jitter: Option<TtlJitter>,
+    tti: Option<Duration>,
+    stale_ttl: Option<Duration>,
+    expire_on_read: bool,
+    write_buffer: usize,
+    slow_sweep: Option<Duration>,
+    negative_ttl: Option<Duration>,
+    error_ttl: Option<Duration>,
+    refresh_after: Option<Duration>,
+    refresher: Option<Refresher<K, V>>,
+    spawner: Option<Spawner>,
+    policy: EvictionPolicy,
+    admission: AdmissionPolicy,
+    pub(crate) listener: Option<EvictionListener<K, V>>,
+    weigher: Option<Weigher<K, V>>,
+    mem_sizer: Option<MemSizer<K, V>>,
+    expiry: Option<Arc<dyn Expiry<K, V>>>,
+    max_weight: Option<u64>,
+    clock: Option<Arc<dyn Clock>>,
+    pub(crate) name: Option<String>,
+    pool: Option<Arc<Pool>>,
+    pub(crate) hasher: S,
+    shard_amount: Option<usize>,
+}
+
+impl<K, V> CacheBuilder<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    /// Create a new builder.
+    pub fn new() -> Self {
+        Self {
+            capacity: None,
+            ttl: None,
+            ttl_jitter: None,
+            tti: None,
+            stale_ttl: None,
+            expire_on_read: true,
+            write_buffer: 0,
+            slow_sweep: None,
+            negative_ttl: None,
+            error_ttl: None,
+            refresh_after: None,
+            refresher: None,
+            spawner: None,
+            policy: EvictionPolicy::Fifo,
+            admission: AdmissionPolicy::Always,
+            listener: None,
+            weigher: None,
+            mem_sizer: None,
+            expiry: None,
+            max_weight: None,
+            clock: None,
+            name: None,
+            pool: None,
+            hasher: ahash::RandomState::new(),
+            shard_amount: None,
+        }
+    }
+
+    /// Seed the default hasher with the given keys, instead of random ones.
+    ///
+    /// By default, every cache hashes its keys with its own `ahash::RandomState`,
+    /// whose keys are derived from random seeds drawn once per process. Explicit
+    /// seeds give a cache keys of its own, e.g. drawn from a secret which differs
+    /// between deployments, and make its hashes reproducible. For keys controlled by
+    /// an attacker, the seeds must stay secret: anyone who knows them can craft keys
+    /// which all collide. The shard of a key in a [`ShardedCache`] is picked with the
+    /// same keys.
+    pub fn hash_seeds(mut self, k0: u64, k1: u64, k2: u64, k3: u64) -> Self {
+        self.hasher = ahash::RandomState::with_seeds(k0, k1, k2, k3);
+        self
+    }
+
+    /// Build a single-threaded cache, without atomics nor locks, see
+    /// [`LocalCache`](crate::LocalCache).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid, see
+    /// [`try_build_local`](Self::try_build_local).
+    pub fn build_local(self) -> crate::LocalCache<K, V> {
+        self.try_build_local().unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Build a single-threaded cache like [`build_local`](Self::build_local), or
+    /// return an error if [`try_build`](Self::try_build) would.
+    ///
+    /// Only the capacity, the TTL, the hash seeds, the eviction listener and the clock
+    /// apply to it, so setting any other option is an
+    /// [`UnsupportedOption`](CacheConfigError::UnsupportedOption) error.
+    pub fn try_build_local(self) -> Result<crate::LocalCache<K, V>, CacheConfigError> {
+        let capacity = self.validate_single_writer()?;
+        let ttl = self.ttl.expect("cache ttl must be set");
+        Ok(crate::LocalCache::from_parts(
+            capacity,
+            ttl,
+            self.listener,
+            self.clock,
+            self.hasher,
+        ))
+    }
+
+    /// Build a cache serving lookups from an immutable snapshot, for read-mostly
+    /// workloads, see [`ReadMostlyCache`](crate::ReadMostlyCache).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid, see
+    /// [`try_build_read_mostly`](Self::try_build_read_mostly).
+    #[cfg(feature = "read-mostly")]
+    pub fn build_read_mostly(self) -> crate::ReadMostlyCache<K, V>
+    where
+        V: Clone,
+    {
+        self.try_build_read_mostly()
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Build a read-mostly cache like [`build_read_mostly`](Self::build_read_mostly),
+    /// or return an error if [`try_build_local`](Self::try_build_local) would: the
+    /// same options apply to it.
+    #[cfg(feature = "read-mostly")]
+    pub fn try_build_read_mostly(self) -> Result<crate::ReadMostlyCache<K, V>, CacheConfigError>
+    where
+        V: Clone,
+    {
+        let capacity = self.validate_single_writer()?;
+        let ttl = self.ttl.expect("cache ttl must be set");
+        Ok(crate::ReadMostlyCache::from_parts(
+            capacity,
+            ttl,
+            self.listener,
+            self.clock,
+            self.hasher,
+        ))
+    }
+
+    /// Check the configuration of a cache written by one thread at a time, which only
+    /// supports the basic options, and return the capacity.
+    fn validate_single_writer(&self) -> Result<usize, CacheConfigError> {
+        let capacity = self.validate()?;
+        let unsupported = [
+            (self.ttl_jitter.is_some(), "ttl_jitter"),
+            (self.tti.is_some(), "time_to_idle"),
+            (self.expiry.is_some(), "expiry"),
+            (self.stale_ttl.is_some(), "stale_ttl"),
+            (!self.expire_on_read, "expire_on_read"),
+            (self.write_buffer > 0, "write_buffer"),
+            (self.negative_ttl.is_some(), "negative_ttl"),
+            (self.error_ttl.is_some(), "error_ttl"),
+            (self.refresh_after.is_some(), "refresh_after"),
+            (self.refresher.is_some(), "refresher"),
+            (self.spawner.is_some(), "spawner"),
+            (self.max_weight.is_some(), "max_weight"),
+            (self.weigher.is_some(), "weigher"),
+            (self.mem_sizer.is_some(), "mem_size"),
+            (self.policy != EvictionPolicy::Fifo, "eviction_policy"),
+            (
+                self.admission != AdmissionPolicy::Always,
+                "admission_policy",
+            ),
+            (self.name.is_some(), "name"),
+            (self.slow_sweep.is_some(), "slow_sweep_threshold"),
+            (self.pool.is_some(), "pool"),
+            (self.shard_amount.is_some(), "shard_amount"),
+        ];
+        match unsupported.into_iter().find(|(set, _)| *set) {
+            Some((_, option)) => Err(CacheConfigError::UnsupportedOption(option)),
+            None => Ok(capacity),
+        }
+    }
+}
+
+impl<K, V, S> CacheBuilder<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    /// Set the maximum number of entries in the cache.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Set the time-to-live (TTL) for values.
+    ///
+    /// Like the TTL given to [`Cache::insert_with_ttl`], it saturates at about a
+    /// century, so `Duration::MAX` makes values never expire.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Make the TTL of each entry shorter by a random amount of up to `jitter`.
+    ///
+    /// Entries inserted around the same time, e.g. right after a deploy, would
+    /// otherwise all expire together and be reloaded at once. With it, each entry
+    /// inserted with the cache-wide TTL expires between `ttl - jitter` and `ttl` after
+    /// it's written, so no entry outlives the TTL. A `jitter` longer than the TTL
+    /// spreads the expirations over the whole TTL. Use
+    /// [`ttl_jitter_fraction`](Self::ttl_jitter_fraction) to set it relative to the
+    /// TTL.
+    pub fn ttl_jitter(mut self, jitter: Duration) -> Self {
+        self.ttl_jitter = Some(TtlJitter::Duration(jitter));
+        self
+    }
+
+    /// Set the [`ttl_jitter`](Self::ttl_jitter) as a fraction of the TTL, e.g. 0.1 for
+    /// entries expiring up to 10% earlier. The fraction is clamped to `[0, 1]`.
+    pub fn ttl_jitter_fraction(mut self, fraction: f64) -> Self {
+        // Also maps NaN to zero.
+        let fraction = if fraction > 0.0 {
+            fraction.min(1.0)
+        } else {
+            0.0
+        };
+        self.ttl_jitter = Some(TtlJitter::Fraction(fraction));
+        self
+    }
+
+    /// Set the time-to-idle (TTI) for values.
+    ///
+    /// An entry which isn't read for this long expires, even if its TTL hasn't passed
+    /// yet. Only the lookups which may refresh the recency of an entry count as reads,
+    /// so `peek` and iteration don't.
+    pub fn time_to_idle(mut self, tti: Duration) -> Self {
+        self.tti = Some(tti);
+        self
+    }
+
+    /// Set an [`Expiry`] computing the expiration of each entry from its key and
+    /// value, instead of the TTL.
+    ///
+    /// Entries are still evicted in insertion order when the cache is full, and like
+    /// entries inserted with [`Cache::insert_with_ttl`], an entry expiring earlier
+    /// than the ones in front of it is only removed once it reaches the head of the
+    /// queue.
+    pub fn expiry(mut self, expiry: impl Expiry<K, V> + 'static) -> Self {
+        self.expiry = Some(Arc::new(expiry));
+        self
+    }
+
+    /// Set how long expired entries are kept and still returned as stale, marked with
+    /// [`Value::is_expired`](crate::Value::is_expired), e.g. as a fallback when
+    /// reloading them fails.
+    ///
+    /// With a TTL of 10 seconds and a stale TTL of 50 seconds, an entry is fresh for
+    /// 10 seconds, then stale until 60 seconds after it was inserted, and then looked
+    /// up as missing and removed. Without it, expired entries are removed by the
+    /// expiration sweep, but the ones which haven't been swept yet are returned no
+    /// matter how old they are.
+    pub fn stale_ttl(mut self, stale_ttl: Duration) -> Self {
+        self.stale_ttl = Some(stale_ttl);
+        self
+    }
+
+    /// Set whether lookups sweep expired entries from the head of the queue.
+    ///
+    /// Defaults to true. Disabling it keeps the latency of lookups flat, as no reader
+    /// ever has to remove a batch of expired entries. Expired entries are still
+    /// reported as such by lookups, and removed by writes, by
+    /// [`Cache::sweep_expired`] or by a [janitor](Cache::spawn_janitor).
+    pub fn expire_on_read(mut self, expire_on_read: bool) -> Self {
+        self.expire_on_read = expire_on_read;
+        self
+    }
+
+    /// Buffer the inserts of new keys in batches of `batch`, instead of queueing each
+    /// of them for eviction right away.
+    ///
+    /// Every new entry takes a slot at the tail of a queue shared by all the threads,
+    /// which becomes contended under bursts of inserts. With a write buffer, each
+    /// thread buffers the slots of its inserts, and queues them all at once when it
+    /// has `batch` of them. The entries themselves are written to the map right
+    /// away, so they're visible to lookups, but they're only counted in the capacity
+    /// once they're queued: the cache may hold up to `batch` extra entries per
+    /// stripe until then, with a stripe per CPU rounded up to a power of two.
+    /// [`Cache::flush_writes`] queues them all. The [maximum
+    /// weight](Self::max_weight) is still enforced on every insert, by queueing the
+    /// buffered entries right away when it's exceeded. Inserts which report their
+    /// evictions, like [`Cache::insert_evicting`], aren't buffered.
+    ///
+    /// Defaults to 0, which disables the buffer.
+    pub fn write_buffer(mut self, batch: usize) -> Self {
+        self.write_buffer = batch;
+        self
+    }
+
+    /// Set the time-to-live (TTL) for keys cached as not existing with
+    /// [`Cache::insert_negative`], which is usually shorter than the one for values.
+    ///
+    /// Defaults to the TTL for values.
+    pub fn negative_ttl(mut self, ttl: Duration) -> Self {
+        self.negative_ttl = Some(ttl);
+        self
+    }
+
+    /// Cache the errors returned by the loaders of
+    /// [`Cache::get_or_try_insert_with`] for the given time-to-live (TTL), which is
+    /// usually much shorter than the one for values, so that a failing upstream
+    /// isn't hit by every lookup.
+    ///
+    /// Defaults to not caching errors.
+    pub fn error_ttl(mut self, ttl: Duration) -> Self {
+        self.error_ttl = Some(ttl);
+        self
+    }
+
+    /// Refresh the entries older than this in the background when they're read with
+    /// [`Cache::get_or_refresh`], before they expire.
+    ///
+    /// Each entry is refreshed up to 10% earlier than this, at a point chosen at
+    /// random, so that entries inserted together don't all reload at once. It has no
+    /// effect without a [`refresher`](Self::refresher), nor if it's not shorter than
+    /// the TTL.
+    pub fn refresh_after(mut self, refresh_after: Duration) -> Self {
+        self.refresh_after = Some(refresh_after);
+        self
+    }
+
+    /// Set the function reloading the value of a key, for
+    /// [`refresh_after`](Self::refresh_after).
+    pub fn refresher(mut self, refresher: impl Fn(&K) -> V + Send + Sync + 'static) -> Self {
+        self.refresher = Some(Arc::new(refresher));
+        self
+    }
+
+    /// Set the function running the background loads and refreshes of
+    /// [`Cache::get_with_refresh`], [`Cache::get_fresh_or_stale`] and
+    /// [`Cache::get_or_refresh`], e.g. to hand them to a thread pool.
+    ///
+    /// The spawner gives the task back if it can't run it, e.g. because its queue is
+    /// full: refreshes are then dropped and the stale value is kept, and the loads of
+    /// `get_fresh_or_stale` run on the calling thread. Defaults to spawning a thread
+    /// per task, with at most 16 of them running at once.
+    pub fn spawner(
+        mut self,
+        spawner: impl Fn(BackgroundTask) -> Result<(), BackgroundTask> + Send + Sync + 'static,
+    ) -> Self {
+        self.spawner = Some(Arc::new(spawner));
+        self
+    }
+
+    /// Set the maximum total weight of the entries in the cache.
+    ///
+    /// When inserting an entry would exceed it, as many of the oldest entries as
+    /// needed are evicted to make room, and an entry heavier than the maximum is not
+    /// inserted at all. The capacity still bounds the number of entries.
+    pub fn max_weight(mut self, max_weight: u64) -> Self {
+        self.max_weight = Some(max_weight);
+        self
+    }
+
+    /// Set a function computing the weight of an entry, for
+    /// [`max_weight`](Self::max_weight).
+    ///
+    /// The weight is computed when an entry is inserted, and again when its value is
+    /// modified in place with [`Cache::modify`] or
+    /// [`CacheEntry::and_modify`](crate::CacheEntry::and_modify), in which case the
+    /// oldest entries are evicted if it no longer fits. Every entry weighs 1 by
+    /// default.
+    pub fn weigher(mut self, weigher: impl Fn(&K, &V) -> u32 + Send + Sync + 'static) -> Self {
+        self.weigher = Some(Arc::new(weigher));
+        self
+    }
+
+    /// Set a function computing the heap memory used by the key and the value of an
+    /// entry, in bytes, for [`Cache::estimated_memory_usage`].
+    ///
+    /// It's called on insertion and when a value is modified in place. Without it,
+    /// only the memory used by the cache itself is estimated.
+    pub fn mem_size(mut self, mem_size: impl Fn(&K, &V) -> usize + Send + Sync + 'static) -> Self {
+        self.mem_sizer = Some(Arc::new(mem_size));
+        self
+    }
+
+    /// Set the policy used to choose which entry to evict when the cache is full.
+    ///
+    /// Defaults to [`EvictionPolicy::Fifo`].
+    pub fn eviction_policy(mut self, policy: EvictionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Set the policy used to decide if a new entry is admitted when the cache is full.
+    ///
+    /// Defaults to [`AdmissionPolicy::Always`].
+    pub fn admission_policy(mut self, admission: AdmissionPolicy) -> Self {
+        self.admission = admission;
+        self
+    }
+
+    /// Set a listener which is called with every entry removed from the cache, and
+    /// why it was removed.
+    ///
+    /// The listener is called after the entry has been removed, on the thread which
+    /// removed it. Expired entries are only reported once they're actually removed,
+    /// which may happen a while after their expiration.
+    pub fn eviction_listener(
+        mut self,
+        listener: impl Fn(&K, &V, RemovalCause) + Send + Sync + 'static,
+    ) -> Self {
+        self.listener = Some(Arc::new(listener));
+        self
+    }
+
+    /// Set the clock used as the source of time, instead of the system clock.
+    ///
+    /// This is mostly useful with a [`MockClock`](crate::MockClock), to control the
+    /// passing of time in tests.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Set the name of the cache.
+    ///
+    /// With the `metrics` feature, it's used as the `cache` label of the metrics, so
+    /// that several caches can be told apart.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Log a warning for every expiration sweep which takes longer than
+    /// `threshold`, to tell whether the sweeps done inline by lookups and writes are
+    /// behind latency spikes.
+    ///
+    /// Sweeps are always traced at the debug level, with the number of entries they
+    /// removed and how long they took. Defaults to no threshold.
+    #[cfg(feature = "tracing")]
+    pub fn slow_sweep_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_sweep = Some(threshold);
+        self
+    }
+
+    /// Set the hasher used to hash the keys, instead of [`ahash`]'s `RandomState`.
+    ///
+    /// E.g. a faster hasher for trusted integer keys, or the standard library's
+    /// `RandomState` for keys controlled by an attacker, as SipHash is designed to
+    /// resist hash flooding. A [sharded cache](Self::build_sharded) also picks the
+    /// shard of a key with it.
+    pub fn hasher<S2>(self, hasher: S2) -> CacheBuilder<K, V, S2>
+    where
+        S2: BuildHasher + Clone,
+    {
+        CacheBuilder {
+            capacity: self.capacity,
+            ttl: self.ttl,
+            ttl_jitter: self.ttl_jitter,
+            tti: self.tti,
+            stale_ttl: self.stale_ttl,
+            expire_on_read: self.expire_on_read,
+            write_buffer: self.write_buffer,
+            slow_sweep: self.slow_sweep,
+            negative_ttl: self.negative_ttl,
+            error_ttl: self.error_ttl,
+            refresh_after: self.refresh_after,
+            refresher: self.refresher,
+            spawner: self.spawner,
+            policy: self.policy,
+            admission: self.admission,
+            listener: self.listener,
+            weigher: self.weigher,
+            mem_sizer: self.mem_sizer,
+            expiry: self.expiry,
+            max_weight: self.max_weight,
+            clock: self.clock,
+            name: self.name,
+            pool: self.pool,
+            hasher,
+            shard_amount: self.shard_amount,
+        }
+    }
+
+    /// Set the number of shards of the underlying [`DashMap`], which must be a power
+    /// of two greater than 1.
+    ///
+    /// Defaults to four times the number of CPUs, rounded up to a power of two. More
+    /// shards means less contention between threads writing to different keys, at the
+    /// cost of a little memory per shard.
+    pub fn shard_amount(mut self, shard_amount: usize) -> Self {
+        self.shard_amount = Some(shard_amount);
+        self
+    }
+
+    /// Share the total weight of a [`CapacityPool`](crate::CapacityPool).
+    pub(crate) fn pool(mut self, pool: Arc<Pool>) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Build the cache.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid, see [`try_build`](Self::try_build).
+    pub fn build(self) -> Cache<K, V, S> {
+        self.try_build().unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Build the cache, or return an error if the capacity or the TTL isn't set, if
+    /// the capacity is too large to be allocated, or if the shard amount isn't a
+    /// power of two greater than 1.
+    pub fn try_build(self) -> Result<Cache<K, V, S>, CacheConfigError> {
+        let capacity = self.validate()?;
+        Ok(self.build_shard(capacity, self.max_weight, None, &self.resolve_spawner()))
+    }
+
+    /// Build a cache split into the given number of shards, see [`ShardedCache`].
+    ///
+    /// The capacity and the maximum weight are divided evenly between the shards,
+    /// rounding up. Everything else is shared by all the shards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid, see
+    /// [`try_build_sharded`](Self::try_build_sharded).
+    pub fn build_sharded(self, shards: usize) -> ShardedCache<K, V, S> {
+        self.try_build_sharded(shards)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Build a sharded cache like [`build_sharded`](Self::build_sharded), or return
+    /// an error if `shards` is zero or if [`try_build`](Self::try_build) would.
+    pub fn try_build_sharded(
+        self,
+        shards: usize,
+    ) -> Result<ShardedCache<K, V, S>, CacheConfigError> {
+        if shards == 0 {
+            return Err(CacheConfigError::ZeroShards);
+        }
+        let capacity = self.validate()?;
+        let max_weight = self.max_weight.map(|w| w.div_ceil(shards as u64));
+        // The shards share the spawner, and so its limit on running threads.
+        let spawner = self.resolve_spawner();
+        Ok(ShardedCache::from_shards(
+            (0..shards)
+                .map(|i| self.build_shard(capacity.div_ceil(shards), max_weight, Some(i), &spawner))
+                .collect(),
+            self.hasher.clone(),
+        ))
+    }
+
+    /// Check the configuration, and return the capacity.
+    fn validate(&self) -> Result<usize, CacheConfigError> {
+        let capacity = self.capacity.ok_or(CacheConfigError::MissingCapacity)?;
+        self.ttl.ok_or(CacheConfigError::MissingTtl)?;
+        // Each slot of the ringbuf also has a state.
+        if capacity > isize::MAX as usize / mem::size_of::<(Slot, usize)>() {
+            return Err(CacheConfigError::CapacityTooLarge);
+        }
+        if self
+            .shard_amount
+            .is_some_and(|n| n < 2 || !n.is_power_of_two())
+        {
+            return Err(CacheConfigError::InvalidShardAmount);
+        }
+        Ok(capacity)
+    }
+
+    /// Get the spawner set with [`spawner`](Self::spawner), or the default one.
+    fn resolve_spawner(&self) -> Spawner {
+        self.spawner.clone().unwrap_or_else(refresh::thread_spawner)
+    }
+
+    fn now(&self) -> Instant {
+        match &self.clock {
+            Some(clock) => clock.now(),
+            None => Instant::now(),
+        }
+    }
+
+    fn build_shard(
+        &self,
+        capacity: usize,
+        max_weight: Option<u64>,
+        shard: Option<usize>,
+        spawner: &Spawner,
+    ) -> Cache<K, V, S> {
+        let ttl = self.ttl.expect("cache ttl must be set");
+        let now = self.now();
+        let prealloc = capacity.min(MAX_PREALLOCATED);
+        let map = match self.shard_amount {
+            Some(n) => {
+                DashMap::with_capacity_and_hasher_and_shard_amount(prealloc, self.hasher.clone(), n)
+            }
+            None => DashMap::with_capacity_and_hasher(prealloc, self.hasher.clone()),
+        };
+        Cache {
+            map,
+            ringbuf: Ringbuf::new(capacity),
+            loading: DashMap::with_hasher(self.hasher.clone()),
+            #[cfg(feature = "async")]
+            loading_async: DashMap::with_hasher(self.hasher.clone()),
+            refreshing: DashSet::with_hasher(self.hasher.clone()),
+            fresh_loads: DashMap::with_hasher(self.hasher.clone()),
+            stats: Counters::new(self.name.as_deref(), shard),
+            trace: Tracer::new(self.name.as_deref(), shard, self.slow_sweep),
+            listener: self.listener.clone(),
+            subscribers: Default::default(),
+            weigher: self.weigher.clone(),
+            mem_sizer: self.mem_sizer.clone(),
+            expiry: self.expiry.clone(),
+            capacity,
+            max_weight,
+            pool: self.pool.clone(),
+            ttl,
+            ttl_jitter: self.ttl_jitter.map(|jitter| match jitter {
+                TtlJitter::Duration(jitter) => jitter,
+                TtlJitter::Fraction(fraction) => ttl.min(expiry::CENTURY).mul_f64(fraction),
+            }),
+            tti: self.tti,
+            stale_ttl: self.stale_ttl,
+            expire_on_read: self.expire_on_read,
+            write_buffer: (self.write_buffer > 0).then(|| WriteBuffer::new(self.write_buffer)),
+            negative_ttl: self.negative_ttl,
+            error_ttl: self.error_ttl,
+            refresh_after: self.refresh_after,
+            refresher: self.refresher.clone(),
+            spawner: spawner.clone(),
+            policy: self.policy,
+            sketch: (self.admission == AdmissionPolicy::TinyLfu)
+                .then(|| FrequencySketch::new(capacity)),
+            // Start at 1, so that version 0 is never handed out.
+            next_id: CachePadded::new(AtomicU64::new(1)),
+            weight: CachePadded::new(AtomicU64::new(0)),
+            entries: CachePadded::new(AtomicUsize::new(0)),
+            mem_size: CachePadded::new(AtomicUsize::new(0)),
+            cleared: CachePadded::new(AtomicU64::new(0)),
+            epoch: AtomicU64::new(0),
+            expire_started: CachePadded::new(AtomicBool::new(false)),
+            oldest: CachePadded::new(AtomicCell::new(now)),
+            created: now,
+            clock: self.clock.clone(),
+            name: self.name.clone(),
+        }
+    }
+}
+
+impl<K, V> Default for CacheBuilder<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,62 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// A source of time for a [`Cache`](crate::Cache).
+pub trait Clock: Send + Sync {
+    /// Get the current instant.
+    fn now(&self) -> Instant;
+}
+
+/// A clock reading the system's monotonic time, which is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock which only moves forward when told to, for deterministic tests.
+///
+/// Clones share the same time, so a clone can be given to a cache and the original
+/// used to advance it.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    start: Instant,
+    // Nanoseconds elapsed since `start`.
+    elapsed: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    /// Create a new clock, starting at the current instant.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Move the clock forward by the given duration.
+    pub fn advance(&self, duration: Duration) {
+        self.elapsed
+            .fetch_add(duration.as_nanos() as u64, Ordering::AcqRel);
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + Duration::from_nanos(self.elapsed.load(Ordering::Acquire))
+    }
+}
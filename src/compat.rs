@@ -0,0 +1,216 @@
+use std::{borrow::Borrow, convert::Infallible, hash::Hash};
+
+use cached::Cached;
+
+use crate::{Cache, CacheEventKind};
+
+/// An adapter implementing the [`cached::Cached`] trait for a [`Cache`], so that it
+/// can be used as the store of the `cached` crate, e.g. by its `#[cached]` macro.
+///
+/// `Cached` hands out references to the values, which can't be borrowed out of a
+/// concurrent cache, so the adapter keeps a copy of the last value it returned. A
+/// value returned mutably is written back in place on the next call on the adapter,
+/// or when the adapter is dropped, unless it was written meanwhile. As
+/// `Cached` takes `&mut self`, use [`cache`](Self::cache) to share the underlying
+/// cache between threads.
+pub struct CachedAdapter<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    cache: Cache<K, V>,
+    // The last value handed out by reference.
+    last: Option<V>,
+    // The key and version of `last`, if it was handed out mutably.
+    dirty: Option<(K, u64)>,
+}
+
+impl<K, V> CachedAdapter<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    /// Wrap the given cache.
+    pub fn new(cache: Cache<K, V>) -> Self {
+        Self {
+            cache,
+            last: None,
+            dirty: None,
+        }
+    }
+
+    /// Get the underlying cache.
+    pub fn cache(&self) -> &Cache<K, V> {
+        &self.cache
+    }
+
+    /// Write the last value returned mutably back to the cache in place, as with
+    /// [`Cache::modify`], unless it was written since.
+    fn write_back(&mut self) {
+        let (Some((key, version)), Some(value)) = (self.dirty.take(), self.last.take()) else {
+            return;
+        };
+        let cache = &self.cache;
+        let Some(mut guard) = cache.map.get_mut(&key).filter(|e| e.version == version) else {
+            return;
+        };
+        let (key, entry) = guard.pair_mut();
+        entry.value = value;
+        let sizes = cache.measure(key, &entry.value);
+        cache.on_modify(entry, sizes);
+        cache.subscribers.emit(CacheEventKind::Update, key);
+        drop(guard);
+        cache.evict_overweight(None);
+    }
+
+    /// Get the key stored in the cache which is equal to the given one.
+    fn stored_key<Q>(&self, k: &Q) -> Option<K>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.cache.live_entry(k).map(|e| e.key().clone())
+    }
+}
+
+impl<K, V> From<Cache<K, V>> for CachedAdapter<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    fn from(cache: Cache<K, V>) -> Self {
+        Self::new(cache)
+    }
+}
+
+impl<K, V> Drop for CachedAdapter<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    fn drop(&mut self) {
+        self.write_back();
+    }
+}
+
+impl<K, V> Cached<K, V> for CachedAdapter<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    type Error = Infallible;
+
+    fn cache_get<Q>(&mut self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.write_back();
+        self.last = self
+            .cache
+            .get(k)
+            .filter(|v| !v.is_expired())
+            .map(|v| v.into_inner());
+        self.last.as_ref()
+    }
+
+    fn cache_get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.write_back();
+        let value = self.cache.get(k).filter(|v| !v.is_expired())?;
+        self.dirty = Some((self.stored_key(k)?, value.version()));
+        Some(self.last.insert(value.into_inner()))
+    }
+
+    fn cache_set(&mut self, k: K, v: V) -> Option<V> {
+        self.write_back();
+        self.cache.replace(k, v)
+    }
+
+    fn cache_get_or_set_with_mut<F: FnOnce() -> V>(&mut self, key: K, f: F) -> &mut V {
+        self.write_back();
+        let value = self.cache.get_or_insert_with(key.clone(), f);
+        self.dirty = Some((key, value.version()));
+        self.last.insert(value.into_inner())
+    }
+
+    fn cache_try_get_or_set_with_mut<F: FnOnce() -> Result<V, E>, E>(
+        &mut self,
+        key: K,
+        f: F,
+    ) -> Result<&mut V, E> {
+        self.write_back();
+        let value = self.cache.try_get_or_insert_with(key.clone(), f)?;
+        self.dirty = Some((key, value.version()));
+        Ok(self.last.insert(value.into_inner()))
+    }
+
+    fn cache_remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.write_back();
+        let live = self.cache.peek(k).is_some_and(|v| !v.is_expired());
+        self.cache.remove(k).filter(|_| live)
+    }
+
+    fn cache_remove_entry<Q>(&mut self, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.write_back();
+        let key = self.stored_key(k)?;
+        let live = self.cache.peek(k).is_some_and(|v| !v.is_expired());
+        self.cache.remove(k).filter(|_| live).map(|v| (key, v))
+    }
+
+    fn cache_clear(&mut self) {
+        self.dirty = None;
+        self.last = None;
+        self.cache.clear();
+    }
+
+    /// The same as [`cache_clear`](Self::cache_clear), as the stats of a [`Cache`]
+    /// can't be reset.
+    fn cache_reset(&mut self) {
+        self.cache_clear();
+    }
+
+    fn cache_size(&self) -> usize {
+        self.cache.len()
+    }
+
+    fn cache_contains<Q>(&mut self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.write_back();
+        self.cache.peek(k).is_some_and(|v| !v.is_expired())
+    }
+
+    fn cache_capacity(&self) -> Option<usize> {
+        Some(self.cache.capacity())
+    }
+
+    #[cfg(feature = "stats")]
+    fn cache_hits(&self) -> Option<u64> {
+        Some(self.cache.stats().hits)
+    }
+
+    #[cfg(feature = "stats")]
+    fn cache_misses(&self) -> Option<u64> {
+        let stats = self.cache.stats();
+        Some(stats.misses + stats.stale_hits)
+    }
+
+    #[cfg(feature = "stats")]
+    fn cache_evictions(&self) -> Option<u64> {
+        Some(self.cache.stats().evictions)
+    }
+}
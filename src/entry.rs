@@ -0,0 +1,221 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    time::Instant,
+};
+
+use dashmap::mapref::entry::Entry as MapEntry;
+
+use crate::{stats::Counter, Cache, CacheEventKind, Entry, RemovalCause, Slot, Value};
+
+/// A view into the entry of a single key in a [`Cache`], obtained with
+/// [`Cache::entry`].
+///
+/// The shard of the key stays locked until the entry is consumed or dropped, so
+/// checking for a value and inserting one is atomic. Other threads accessing keys in
+/// the same shard wait meanwhile, and the cache must not be accessed from the same
+/// thread or it will deadlock.
+pub struct CacheEntry<'a, K, V, S = ahash::RandomState>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    cache: &'a Cache<K, V, S>,
+    // Only taken when the entry is consumed.
+    entry: Option<MapEntry<'a, K, Entry<V>>>,
+    now: Instant,
+    // Whether the value was modified, so the cache may be overweight once the shard
+    // is unlocked.
+    modified: bool,
+}
+
+impl<K, V, S> Cache<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    /// Insert a key-value pair only if `cond` returns true for the current value
+    /// associated with the key, and return whether it was inserted.
+    ///
+    /// `cond` is given `None` if there is no value or if it's expired, and it's called
+    /// while holding the lock on the shard, so that no other write can happen between
+    /// the check and the insert, e.g. to only overwrite a value with a newer one. It
+    /// must not access the cache. Like the [entry](Self::entry) API, the value doesn't
+    /// go through the admission policy.
+    pub fn insert_if(&self, key: K, value: V, cond: impl FnOnce(Option<&V>) -> bool) -> bool {
+        let entry = self.entry(key);
+        if !cond(entry.get()) {
+            return false;
+        }
+        entry.put(value).0 != 0
+    }
+
+    /// Get the entry of the given key, to check for a value and insert one atomically.
+    ///
+    /// Expired values are treated as if they were missing, as with
+    /// [`get_or_insert_with`](Self::get_or_insert_with).
+    pub fn entry(&self, key: K) -> CacheEntry<'_, K, V, S> {
+        let now = self.now();
+        self.record_lookup(&key);
+        // Expire first, as it can't be done while the shard is locked.
+        self.do_expire(now);
+        CacheEntry {
+            cache: self,
+            entry: Some(self.map.entry(key)),
+            now,
+            modified: false,
+        }
+    }
+}
+
+impl<K, V, S> CacheEntry<'_, K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    /// Get the key of the entry.
+    pub fn key(&self) -> &K {
+        self.map_entry().key()
+    }
+
+    /// Check if the key has a value which isn't expired.
+    pub fn is_present(&self) -> bool {
+        match self.map_entry() {
+            MapEntry::Occupied(o) => {
+                let e = o.get();
+                !self.cache.is_cleared(e.id) && self.now <= self.cache.deadline(e)
+            }
+            MapEntry::Vacant(_) => false,
+        }
+    }
+
+    /// Get the value if it's present, without counting it as a read.
+    pub fn get(&self) -> Option<&V> {
+        match self.map_entry() {
+            MapEntry::Occupied(o) if self.is_present() => Some(&o.get().value),
+            _ => None,
+        }
+    }
+
+    /// Call `f` with a mutable reference to the value if it's present, as with
+    /// [`Cache::modify`].
+    ///
+    /// The entry is weighed again, and if the cache no longer fits in its maximum
+    /// weight, the oldest entries are evicted once the entry is dropped or consumed.
+    pub fn and_modify(mut self, f: impl FnOnce(&mut V)) -> Self {
+        if !self.is_present() {
+            return self;
+        }
+        let (cache, now) = (self.cache, self.now);
+        if let Some(MapEntry::Occupied(o)) = &mut self.entry {
+            cache.read_entry(o.key(), o.get(), now);
+            f(&mut o.get_mut().value);
+            let sizes = cache.measure(o.key(), &o.get().value);
+            cache.on_modify(o.get_mut(), sizes);
+            cache.subscribers.emit(CacheEventKind::Update, o.key());
+            self.modified = true;
+        }
+        self
+    }
+
+    fn map_entry(&self) -> &MapEntry<'_, K, Entry<V>> {
+        // It's only taken by the methods consuming the entry.
+        self.entry.as_ref().unwrap()
+    }
+
+    /// Insert the value, replacing the present one if any, and return its version
+    /// and expiration. The version is zero if the value was rejected.
+    fn put(mut self, value: V) -> (u64, Instant) {
+        let (cache, now) = (self.cache, self.now);
+        let mut entry = self.entry.take().unwrap();
+        let key = entry.key().clone();
+        let expire_at = cache.ttl_deadline(&key, now);
+        let weight = cache.weigher.as_ref().map_or(1, |w| w(&key, &value));
+        if cache.is_disabled() || cache.is_too_heavy(weight) {
+            drop(entry);
+            cache.notify(&key, &value, RemovalCause::Size);
+            return (0, expire_at);
+        }
+        // A present value is updated in place, keeping its slot and its priority.
+        if let MapEntry::Occupied(o) = &mut entry {
+            if !cache.is_cleared(o.get().id) {
+                let (old, version, expire_at) =
+                    cache.update_entry(&key, o.get_mut(), value, weight, now, expire_at, None);
+                drop(entry);
+                cache.on_update(&key, &old, None);
+                return (version, expire_at);
+            }
+        }
+        let new = cache.new_entry(&key, value, weight, now, expire_at, None);
+        let (id, expire_at) = (new.id, new.expire_at);
+        let old = match entry {
+            MapEntry::Occupied(mut o) => Some(o.insert(new)),
+            MapEntry::Vacant(v) => {
+                v.insert(new);
+                None
+            }
+        };
+        // The shard is unlocked now. The entry replaced was cleared, so it's reported
+        // as removed rather than replaced.
+        if let Some(old) = old {
+            cache.on_removal(&key, &old, RemovalCause::Explicit);
+        }
+        cache.subscribers.emit(CacheEventKind::Insert, &key);
+        cache.stats.record(Counter::Insertion);
+        let hash = cache.hash_key(&key);
+        cache.push_slot(Slot { hash, id }, None);
+        (id, expire_at)
+    }
+}
+
+impl<K, V, S> Drop for CacheEntry<'_, K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    fn drop(&mut self) {
+        // Unlock the shard first, as evicting may need it.
+        drop(self.entry.take());
+        if self.modified {
+            self.cache.evict_overweight(None);
+        }
+    }
+}
+
+impl<K, V, S> CacheEntry<'_, K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    /// Get the value if it's present, or insert the given one.
+    pub fn or_insert(self, value: V) -> Value<V> {
+        self.or_insert_with(|| value)
+    }
+
+    /// Get the value if it's present, or insert the one returned by `f`.
+    ///
+    /// `f` is called while holding the lock on the shard, so it must not access the
+    /// cache. The inserted value doesn't go through the admission policy, as the
+    /// victim it would be compared with can't be looked up meanwhile.
+    pub fn or_insert_with(self, f: impl FnOnce() -> V) -> Value<V> {
+        let (cache, now) = (self.cache, self.now);
+        if let (true, MapEntry::Occupied(o)) = (self.is_present(), self.map_entry()) {
+            cache.stats.record(Counter::Hit);
+            let expire_at = cache.read_entry(o.key(), o.get(), now);
+            return o.get().to_value(expire_at, now);
+        }
+        cache.stats.record(Counter::Miss);
+        self.insert(f())
+    }
+
+    /// Insert the given value, replacing the present one if any.
+    ///
+    /// As with [`Cache::insert`], a present value is updated in place and keeps its
+    /// position in the queue. Like [`or_insert_with`](Self::or_insert_with), the
+    /// value doesn't go through the admission policy.
+    pub fn insert(self, value: V) -> Value<V> {
+        let now = self.now;
+        let (version, expire_at) = self.put(value.clone());
+        Value::inserted(value, now, expire_at, version)
+    }
+}
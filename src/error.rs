@@ -0,0 +1,63 @@
+use std::{error::Error, fmt};
+
+/// The error returned by [`Cache::try_insert`](crate::Cache::try_insert) when there
+/// is no room for a new entry without evicting a live one.
+pub struct CacheFull<K, V> {
+    /// The key which wasn't inserted.
+    pub key: K,
+    /// The value which wasn't inserted.
+    pub value: V,
+}
+
+impl<K, V> fmt::Debug for CacheFull<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CacheFull").finish_non_exhaustive()
+    }
+}
+
+impl<K, V> fmt::Display for CacheFull<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("cache is full")
+    }
+}
+
+impl<K, V> Error for CacheFull<K, V> {}
+
+/// The error returned when building a cache with an invalid configuration, e.g.
+/// by [`Cache::try_new`](crate::Cache::try_new) or
+/// [`CacheBuilder::try_build`](crate::CacheBuilder::try_build).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CacheConfigError {
+    /// The capacity wasn't set.
+    MissingCapacity,
+    /// The time-to-live wasn't set.
+    MissingTtl,
+    /// The capacity is too large to allocate the queue of the cache.
+    CapacityTooLarge,
+    /// A sharded cache was built with zero shards.
+    ZeroShards,
+    /// The shard amount of the map isn't a power of two greater than 1.
+    InvalidShardAmount,
+    /// An option which the kind of cache being built doesn't support was set, e.g.
+    /// a weigher for a [`LocalCache`](crate::LocalCache). Holds the name of the
+    /// builder method.
+    UnsupportedOption(&'static str),
+}
+
+impl fmt::Display for CacheConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::MissingCapacity => "cache capacity must be set",
+            Self::MissingTtl => "cache ttl must be set",
+            Self::CapacityTooLarge => "cache capacity is too large",
+            Self::ZeroShards => "cache shards must not be zero",
+            Self::InvalidShardAmount => "cache shard amount must be a power of two greater than 1",
+            Self::UnsupportedOption(option) => {
+                return write!(f, "cache option `{option}` isn't supported by this cache");
+            }
+        })
+    }
+}
+
+impl Error for CacheConfigError {}
@@ -0,0 +1,124 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, PoisonError, RwLock,
+    },
+};
+
+use crate::Cache;
+
+/// What happened to an entry, see [`CacheEvent`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheEventKind {
+    /// A value was inserted for a key which wasn't in the cache.
+    Insert,
+    /// The value of a key was replaced or modified in place.
+    Update,
+    /// The entry was removed by `remove`, `invalidate` or `clear`.
+    Remove,
+    /// The entry was removed because it was expired.
+    Expire,
+    /// The entry was evicted to make room, or a new value wasn't admitted.
+    Evict,
+}
+
+/// A mutation of a cache, which is sent to its subscribers.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheEvent<'a, K> {
+    /// What happened to the entry.
+    pub kind: CacheEventKind,
+    /// The key of the entry.
+    pub key: &'a K,
+}
+
+/// The id of a subscriber, to [unsubscribe](Cache::unsubscribe) it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(pub(crate) u64);
+
+pub(crate) type Subscriber<K> = Arc<dyn Fn(&CacheEvent<'_, K>) + Send + Sync>;
+
+/// The subscribers of a cache.
+pub(crate) struct Subscribers<K> {
+    // Checked first, so that events cost nothing without subscribers.
+    active: AtomicBool,
+    next_id: AtomicU64,
+    list: RwLock<Vec<(u64, Subscriber<K>)>>,
+}
+
+impl<K> Default for Subscribers<K> {
+    fn default() -> Self {
+        Self {
+            active: AtomicBool::new(false),
+            next_id: AtomicU64::new(0),
+            list: RwLock::new(Vec::new()),
+        }
+    }
+}
+
+impl<K> Subscribers<K> {
+    pub(crate) fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub(crate) fn emit(&self, kind: CacheEventKind, key: &K) {
+        if !self.is_active() {
+            return;
+        }
+        let event = CacheEvent { kind, key };
+        let list = self.list.read().unwrap_or_else(PoisonError::into_inner);
+        for (_, subscriber) in list.iter() {
+            subscriber(&event);
+        }
+    }
+
+    /// Add a subscriber under the given id, or a new one, and return its id.
+    pub(crate) fn add(&self, id: Option<u64>, f: Subscriber<K>) -> u64 {
+        let id = match id {
+            Some(id) => {
+                self.next_id.fetch_max(id + 1, Ordering::Relaxed);
+                id
+            }
+            None => self.next_id.fetch_add(1, Ordering::Relaxed),
+        };
+        let mut list = self.list.write().unwrap_or_else(PoisonError::into_inner);
+        list.push((id, f));
+        self.active.store(true, Ordering::Release);
+        id
+    }
+
+    /// Remove the subscriber with the given id, and return whether it was found.
+    pub(crate) fn remove(&self, id: u64) -> bool {
+        let mut list = self.list.write().unwrap_or_else(PoisonError::into_inner);
+        let len = list.len();
+        list.retain(|(i, _)| *i != id);
+        self.active.store(!list.is_empty(), Ordering::Release);
+        list.len() != len
+    }
+}
+
+impl<K, V, S> Cache<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    /// Subscribe to the mutations of the cache, e.g. to forward invalidations to
+    /// other nodes.
+    ///
+    /// `f` is called with every event, on the thread which caused it and possibly
+    /// while the shard of the key is locked, so it must not access the cache nor
+    /// subscribe or unsubscribe. Like the eviction listener, expired entries are only
+    /// reported once they're actually removed.
+    pub fn subscribe(
+        &self,
+        f: impl Fn(&CacheEvent<'_, K>) + Send + Sync + 'static,
+    ) -> SubscriptionId {
+        SubscriptionId(self.subscribers.add(None, Arc::new(f)))
+    }
+
+    /// Remove a subscriber, and return whether it was found.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        self.subscribers.remove(id.0)
+    }
+}
@@ -0,0 +1,51 @@
+use std::time::{Duration, Instant};
+
+/// Computes the expiration of each entry from its key and value instead of using
+/// the cache-wide TTL, e.g. to follow the expiry embedded in a token. Set it with
+/// [`CacheBuilder::expiry`](crate::CacheBuilder::expiry).
+///
+/// Every hook returns how long the entry has left to live from `now`, or `None` to
+/// keep the expiration the cache would use without it. The time-to-idle still
+/// applies on top. Hooks are called while the shard of the entry is locked, so they
+/// must not access the cache.
+pub trait Expiry<K, V>: Send + Sync {
+    /// Called when an entry is created. `None` keeps the TTL it was inserted with,
+    /// which is the cache-wide one unless another was given.
+    fn expire_after_create(&self, _key: &K, _value: &V, _now: Instant) -> Option<Duration> {
+        None
+    }
+
+    /// Called when the value of an entry is replaced, with how long the previous one
+    /// had left to live. `None` keeps the TTL it was inserted with.
+    fn expire_after_update(
+        &self,
+        _key: &K,
+        _value: &V,
+        _now: Instant,
+        _remaining: Duration,
+    ) -> Option<Duration> {
+        None
+    }
+
+    /// Called when a value which isn't expired is read, with how long it has left to
+    /// live. `None` leaves its expiration unchanged.
+    fn expire_after_read(
+        &self,
+        _key: &K,
+        _value: &V,
+        _now: Instant,
+        _remaining: Duration,
+    ) -> Option<Duration> {
+        None
+    }
+}
+
+/// The longest duration an entry is kept for, which is far enough in the future to
+/// never be reached in practice.
+pub(crate) const CENTURY: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// Add a ttl to `now`, saturating at about a century from now so that
+/// `Duration::MAX` can be used for entries which never expire.
+pub(crate) fn expire_after(now: Instant, duration: Duration) -> Instant {
+    now + duration.min(CENTURY)
+}
@@ -0,0 +1,52 @@
+use std::hash::{BuildHasher, Hash};
+
+use crate::{Cache, Value};
+
+/// Caching the results of fallible loaders: values are cached with the ttl of the
+/// cache, and errors with a ttl of their own, or not at all.
+impl<K, V, E, S> Cache<K, Result<V, E>, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    E: Clone,
+    S: BuildHasher + Clone,
+{
+    /// Get the value associated with the given key, or load it with `f` and insert
+    /// it if it doesn't exist or is expired.
+    ///
+    /// An error returned by `f` is cached for the ttl set with
+    /// [`CacheBuilder::error_ttl`](crate::CacheBuilder::error_ttl), and returned by
+    /// the lookups of the key until then. Without it, errors aren't cached, and
+    /// callers waiting on the same key will run their own `f`. Concurrent calls for
+    /// the same key are deduplicated like with
+    /// [`get_or_insert_with`](Self::get_or_insert_with).
+    pub fn get_or_try_insert_with(
+        &self,
+        key: K,
+        f: impl FnOnce() -> Result<V, E>,
+    ) -> Result<Value<V>, E> {
+        let loaded = self.load(key, || match f() {
+            Ok(value) => Ok((Ok(value), None)),
+            Err(e) => match self.error_ttl {
+                Some(ttl) => Ok((Err(e), Some(ttl))),
+                None => Err(e),
+            },
+        })?;
+        let Value {
+            value,
+            expire_at,
+            is_expired,
+            inserted_at,
+            read_at,
+            version,
+        } = loaded;
+        value.map(|value| Value {
+            value,
+            expire_at,
+            is_expired,
+            inserted_at,
+            read_at,
+            version,
+        })
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    borrow::Borrow,
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+use crate::{builder::MAX_PREALLOCATED, RemovalCause, Value};
+
+/// The map of a single-writer cache, see [`Fifo`].
+pub(crate) type Map<K, V> = HashMap<K, Entry<V>, ahash::RandomState>;
+
+/// An entry of a single-writer cache.
+#[derive(Clone)]
+pub(crate) struct Entry<V> {
+    pub(crate) value: V,
+    pub(crate) expire_at: Instant,
+    inserted_at: Instant,
+    // The id of the slot of the entry in the queue.
+    id: u64,
+    version: u64,
+}
+
+impl<V: Clone> Entry<V> {
+    pub(crate) fn to_value(&self, now: Instant) -> Value<V> {
+        Value {
+            value: self.value.clone(),
+            expire_at: self.expire_at,
+            is_expired: now > self.expire_at,
+            inserted_at: self.inserted_at,
+            read_at: now,
+            version: self.version,
+        }
+    }
+}
+
+/// The writer side of the caches which are only written by one thread at a time,
+/// [`LocalCache`](crate::LocalCache) and
+/// [`ReadMostlyCache`](crate::ReadMostlyCache): the queue of the keys of a map in
+/// insertion order, which evictions and expirations pop from.
+///
+/// The map is passed to each method rather than owned, so that the read-mostly
+/// cache can write to a copy of its snapshot.
+pub(crate) struct Fifo<K, V> {
+    // The keys in insertion order, with the id of the entry they were queued with.
+    queue: VecDeque<(K, u64)>,
+    // The entries removed by the current write, to be reported once it's done.
+    pub(crate) removed: Vec<(K, V, RemovalCause)>,
+    next_id: u64,
+    capacity: usize,
+    // Set when the capacity or the TTL is zero, so nothing is stored.
+    disabled: bool,
+}
+
+impl<K, V> Fifo<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    pub(crate) fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            queue: VecDeque::with_capacity(capacity.min(MAX_PREALLOCATED)),
+            removed: Vec::new(),
+            next_id: 1,
+            capacity,
+            disabled: capacity == 0 || ttl.is_zero(),
+        }
+    }
+
+    /// Create an empty map for a cache with the given capacity and hasher.
+    pub(crate) fn map(capacity: usize, hasher: ahash::RandomState) -> Map<K, V> {
+        HashMap::with_capacity_and_hasher(capacity.min(MAX_PREALLOCATED), hasher)
+    }
+
+    /// Remove the expired entries at the head of the queue.
+    pub(crate) fn expire(&mut self, map: &mut Map<K, V>, now: Instant) {
+        while let Some((k, id)) = self.queue.front() {
+            match map.get(k) {
+                Some(e) if e.id == *id && now <= e.expire_at => break,
+                Some(e) if e.id == *id => {
+                    let (k, _) = self.queue.pop_front().unwrap();
+                    let e = map.remove(&k).unwrap();
+                    self.removed.push((k, e.value, RemovalCause::Expired));
+                }
+                _ => {
+                    self.queue.pop_front();
+                }
+            }
+        }
+    }
+
+    /// Insert an entry, evicting the oldest ones if the map is full, and return its
+    /// version, or zero if it was rejected.
+    ///
+    /// An existing key is updated in place, and keeps its position in the queue.
+    pub(crate) fn insert(
+        &mut self,
+        map: &mut Map<K, V>,
+        key: K,
+        value: V,
+        now: Instant,
+        expire_at: Instant,
+    ) -> u64 {
+        if self.disabled {
+            self.removed.push((key, value, RemovalCause::Size));
+            return 0;
+        }
+        let version = self.next_id;
+        self.next_id += 1;
+        if let Some(e) = map.get_mut(&key) {
+            let old = std::mem::replace(&mut e.value, value);
+            e.expire_at = expire_at;
+            e.inserted_at = now;
+            e.version = version;
+            self.removed.push((key, old, RemovalCause::Replaced));
+            return version;
+        }
+        while map.len() >= self.capacity {
+            let Some((k, id)) = self.queue.pop_front() else {
+                break;
+            };
+            if map.get(&k).is_some_and(|e| e.id == id) {
+                let e = map.remove(&k).unwrap();
+                self.removed.push((k, e.value, RemovalCause::Size));
+            }
+        }
+        self.queue.push_back((key.clone(), version));
+        map.insert(
+            key,
+            Entry {
+                value,
+                expire_at,
+                inserted_at: now,
+                id: version,
+                version,
+            },
+        );
+        version
+    }
+
+    /// Remove the entry associated with the given key from the map.
+    ///
+    /// Its slot is left in the queue and skipped once it reaches the head, unless
+    /// there are more than twice as many slots as entries, in which case the ones
+    /// left behind are all dropped.
+    pub(crate) fn remove<Q>(&mut self, map: &mut Map<K, V>, key: &Q) -> Option<(K, Entry<V>)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let removed = map.remove_entry(key)?;
+        if self.queue.len() > 2 * map.len() + 16 {
+            self.queue
+                .retain(|(k, id)| map.get::<K>(k).is_some_and(|e| e.id == *id));
+        }
+        Some(removed)
+    }
+
+    /// Remove all the entries of the map.
+    pub(crate) fn clear(&mut self, map: &mut Map<K, V>) {
+        self.queue.clear();
+        self.removed.extend(
+            map.drain()
+                .map(|(k, e)| (k, e.value, RemovalCause::Explicit)),
+        );
+    }
+}
@@ -0,0 +1,58 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    hash::{BuildHasher, Hash},
+};
+
+use crate::{Cache, Loading, Value};
+
+impl<K, V, S> Cache<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    /// Get the value associated with the given key, or await `loader` and insert its
+    /// output if it doesn't exist or is expired.
+    ///
+    /// Concurrent loads for the same key are coalesced: only one `loader` is awaited,
+    /// and the other callers wait for it and return the value it inserted. If the
+    /// awaiting task is cancelled, one of the waiting callers takes over with its own
+    /// `loader`.
+    pub async fn get_or_load(&self, key: K, loader: impl Future<Output = V>) -> Value<V> {
+        let loader = async { Ok::<_, Infallible>(loader.await) };
+        match self.try_get_or_load(key, loader).await {
+            Ok(value) => value,
+            Err(e) => match e {},
+        }
+    }
+
+    /// Like [`get_or_load`](Self::get_or_load), but `loader` may fail.
+    ///
+    /// If `loader` returns an error nothing is inserted and the error is returned;
+    /// callers waiting on the same key will then await their own `loader`.
+    pub async fn try_get_or_load<E>(
+        &self,
+        key: K,
+        loader: impl Future<Output = Result<V, E>>,
+    ) -> Result<Value<V>, E> {
+        if let Some(value) = self.get(&key).filter(|v| !v.is_expired()) {
+            return Ok(value);
+        }
+
+        let loading = Loading::new(&self.loading_async, &key);
+        let guard = loading.lock.lock().await;
+        // Someone else may have inserted it while we were waiting.
+        let result = match self.peek(&key).filter(|v| !v.is_expired()) {
+            Some(value) => Ok(value),
+            None => loader.await.map(|value| {
+                let now = self.now();
+                let expire_at = self.ttl_deadline(&key, now);
+                let inserted = self.insert_inner(key.clone(), value.clone(), now, expire_at);
+                Value::inserted(value, now, inserted.expire_at, inserted.version)
+            }),
+        };
+        drop(guard);
+        result
+    }
+}
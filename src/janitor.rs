@@ -0,0 +1,38 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::Cache;
+
+impl<K, V, S> Cache<K, V, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    S: BuildHasher + Clone + Send + Sync + 'static,
+{
+    /// Spawn a thread calling [`evict_expired`](Self::evict_expired) every `interval`,
+    /// so expired entries are dropped even when the cache isn't used.
+    ///
+    /// The thread only holds a weak reference, and stops once the cache is dropped.
+    pub fn spawn_janitor(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        spawn(self, interval, Self::evict_expired)
+    }
+}
+
+/// Spawn a thread calling `f` with the cache every `interval`, until it's dropped.
+pub(crate) fn spawn<C>(cache: &Arc<C>, interval: Duration, f: fn(&C)) -> JoinHandle<()>
+where
+    C: Send + Sync + 'static,
+{
+    let cache = Arc::downgrade(cache);
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        match cache.upgrade() {
+            Some(cache) => f(&cache),
+            None => return,
+        }
+    })
+}
@@ -1,25 +1,120 @@
 #![cfg_attr(not(doctest), doc = include_str!("../README.md"))]
 
 use std::{
-    hash::Hash,
-    sync::atomic::{AtomicBool, Ordering},
+    borrow::Borrow,
+    convert::Infallible,
+    hash::{BuildHasher, Hash},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
     time::{Duration, Instant},
 };
 
 use crossbeam_queue::ArrayQueue;
 use crossbeam_utils::{atomic::AtomicCell, CachePadded};
-use dashmap::DashMap;
+use dashmap::{
+    mapref::{entry::Entry as MapEntry, one::Ref},
+    DashMap, DashSet,
+};
+
+mod buffer;
+mod builder;
+mod clock;
+#[cfg(feature = "cached-compat")]
+mod compat;
+mod entry;
+mod error;
+mod events;
+mod expiry;
+mod fallible;
+mod fifo;
+#[cfg(feature = "async")]
+mod future;
+mod janitor;
+mod listener;
+mod local;
+mod negative;
+#[cfg(feature = "serde")]
+mod persist;
+mod policy;
+mod pool;
+#[cfg(feature = "prometheus")]
+mod prom;
+#[cfg(feature = "read-mostly")]
+mod read_mostly;
+mod refresh;
+mod registry;
+mod ringbuf;
+mod scope;
+mod sharded;
+mod sketch;
+mod stats;
+mod tiered;
+mod trace;
+
+use buffer::WriteBuffer;
+pub use builder::CacheBuilder;
+use builder::{MemSizer, Refresher, Spawner, Weigher};
+pub use clock::{Clock, MockClock, SystemClock};
+#[cfg(feature = "cached-compat")]
+pub use compat::CachedAdapter;
+pub use entry::CacheEntry;
+pub use error::{CacheConfigError, CacheFull};
+use events::Subscribers;
+pub use events::{CacheEvent, CacheEventKind, SubscriptionId};
+pub use expiry::Expiry;
+use listener::EvictionListener;
+pub use listener::RemovalCause;
+pub use local::LocalCache;
+pub use negative::CacheResult;
+pub use policy::{AdmissionPolicy, EvictionPolicy, Priority};
+pub use pool::CapacityPool;
+use pool::Pool;
+#[cfg(feature = "read-mostly")]
+pub use read_mostly::ReadMostlyCache;
+pub use refresh::BackgroundTask;
+use refresh::FreshLoad;
+pub use registry::CacheRegistry;
+use ringbuf::Ringbuf;
+pub use scope::{Scope, ScopedKey};
+pub use sharded::ShardedCache;
+use sketch::FrequencySketch;
+#[cfg(feature = "stats")]
+pub use stats::CacheStats;
+use stats::{Counter, Counters};
+#[cfg(feature = "async")]
+pub use tiered::AsyncStore;
+pub use tiered::{Store, TieredCache, WritePolicy};
+use trace::{Pressure, Tracer};
 
 /// Represents an entry in the cache.
 ///
 /// Wraps a value with an expiration timestamp and an expired flag.
+#[derive(Clone)]
 pub struct Value<V> {
     value: V,
     expire_at: Instant,
     is_expired: bool,
+    inserted_at: Instant,
+    // When the value was looked up, according to the clock of the cache.
+    read_at: Instant,
+    version: u64,
 }
 
 impl<V> Value<V> {
+    /// Create the value returned right after inserting it.
+    fn inserted(value: V, now: Instant, expire_at: Instant, version: u64) -> Self {
+        Self {
+            value,
+            expire_at,
+            is_expired: now > expire_at,
+            inserted_at: now,
+            read_at: now,
+            version,
+        }
+    }
+
     /// Get a reference to the inner value.
     pub fn get(&self) -> &V {
         &self.value
@@ -44,6 +139,29 @@ impl<V> Value<V> {
     pub fn expire_at(&self) -> Instant {
         self.expire_at
     }
+
+    /// Get how long the value had been in the cache when it was looked up.
+    ///
+    /// Like [`remaining`](Self::remaining), this is computed with the clock of the
+    /// cache at the time of the lookup, so it doesn't change as the `Value` is held.
+    pub fn age(&self) -> Duration {
+        self.read_at.saturating_duration_since(self.inserted_at)
+    }
+
+    /// Get how long the value had left to live when it was looked up, which is zero
+    /// if it was expired.
+    pub fn remaining(&self) -> Duration {
+        self.expire_at.saturating_duration_since(self.read_at)
+    }
+
+    /// Get the version of the value, for [`Cache::compare_and_insert`].
+    ///
+    /// Every write of a value gets a new version, which is never reused by the same
+    /// cache, even for another key. The version is zero if the value was rejected
+    /// instead of being inserted.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
 }
 
 impl<V> std::ops::Deref for Value<V> {
@@ -60,38 +178,309 @@ impl<V> std::ops::DerefMut for Value<V> {
     }
 }
 
+/// A reference to an entry in the cache, returned by [`Cache::get_ref`].
+///
+/// It holds a read lock on the shard of the entry, so it should be dropped as soon
+/// as possible. Writing to the cache from the same thread while holding it may
+/// deadlock.
+pub struct ValueRef<'a, K, V> {
+    entry: Ref<'a, K, Entry<V>>,
+    expire_at: Instant,
+    is_expired: bool,
+    read_at: Instant,
+}
+
+impl<K: Eq + Hash, V> ValueRef<'_, K, V> {
+    /// Get a reference to the key of the entry.
+    pub fn key(&self) -> &K {
+        self.entry.key()
+    }
+
+    /// Get a reference to the inner value.
+    pub fn get(&self) -> &V {
+        &self.entry.value
+    }
+
+    /// Check if the value is expired.
+    pub fn is_expired(&self) -> bool {
+        self.is_expired
+    }
+
+    /// Get the expiration timestamp of the value.
+    pub fn expire_at(&self) -> Instant {
+        self.expire_at
+    }
+
+    /// Get how long the value had been in the cache when it was looked up, see
+    /// [`Value::age`].
+    pub fn age(&self) -> Duration {
+        self.read_at
+            .saturating_duration_since(self.entry.inserted_at)
+    }
+
+    /// Get how long the value had left to live when it was looked up, see
+    /// [`Value::remaining`].
+    pub fn remaining(&self) -> Duration {
+        self.expire_at.saturating_duration_since(self.read_at)
+    }
+
+    /// Get the version of the value, see [`Value::version`].
+    pub fn version(&self) -> u64 {
+        self.entry.version
+    }
+}
+
+impl<K: Eq + Hash, V> std::ops::Deref for ValueRef<'_, K, V> {
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entry.value
+    }
+}
+
+/// The expiration metadata of an entry, returned by [`Cache::get_with`] next to
+/// the projection of its value.
+#[derive(Clone, Copy, Debug)]
+pub struct EntryMeta {
+    expire_at: Instant,
+    is_expired: bool,
+    inserted_at: Instant,
+    read_at: Instant,
+    version: u64,
+}
+
+impl EntryMeta {
+    /// Check if the value is expired.
+    pub fn is_expired(&self) -> bool {
+        self.is_expired
+    }
+
+    /// Get the expiration timestamp of the value.
+    pub fn expire_at(&self) -> Instant {
+        self.expire_at
+    }
+
+    /// Get how long the value had been in the cache when it was looked up, see
+    /// [`Value::age`].
+    pub fn age(&self) -> Duration {
+        self.read_at.saturating_duration_since(self.inserted_at)
+    }
+
+    /// Get how long the value had left to live when it was looked up, see
+    /// [`Value::remaining`].
+    pub fn remaining(&self) -> Duration {
+        self.expire_at.saturating_duration_since(self.read_at)
+    }
+
+    /// Get the version of the value, see [`Value::version`].
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+/// An entry in the map of a [`Cache`].
+struct Entry<V> {
+    value: V,
+    expire_at: Instant,
+    inserted_at: Instant,
+    // The id of the ringbuf slot which owns this entry.
+    id: u64,
+    // Starts as the id, and is taken from the same counter on every update.
+    version: u64,
+    // Set when the entry is read, to give it a second chance in lru mode.
+    visited: AtomicBool,
+    // Set for entries with the pinned priority, which are not evicted.
+    pinned: AtomicBool,
+    // When the entry was last read, in nanoseconds since the cache was created.
+    // Only maintained with a time-to-idle.
+    accessed: AtomicU64,
+    // Replaces `expire_at` when set by `Expiry::expire_after_read`, in nanoseconds
+    // since the cache was created, or `u64::MAX` if unset.
+    read_expire_at: AtomicU64,
+    weight: u32,
+    // The heap memory used by the key and value, only known with a memory sizer.
+    mem_size: usize,
+}
+
+impl<V: Clone> Entry<V> {
+    fn to_value(&self, expire_at: Instant, now: Instant) -> Value<V> {
+        Value {
+            value: self.value.clone(),
+            expire_at,
+            is_expired: now > expire_at,
+            inserted_at: self.inserted_at,
+            read_at: now,
+            version: self.version,
+        }
+    }
+}
+
+/// Where the entries evicted by an insert are moved to, if the caller wants them.
+type Sink<'a, K, V> = Option<&'a mut Vec<(K, V)>>;
+
+/// What was done by an insert into a [`Cache`].
+struct Inserted<V> {
+    // The previous value of the key, unless it was cleared.
+    old: Option<V>,
+    // The version of the new value, zero if it was rejected.
+    version: u64,
+    expire_at: Instant,
+}
+
+/// A slot in the ringbuf of a [`Cache`].
+///
+/// A slot only owns the entry inserted together with it, so slots left behind by
+/// removed or replaced entries are skipped when they're popped. It finds its entry
+/// by the hash of the key and the id, so the key is only stored once, in the map.
+struct Slot {
+    hash: u64,
+    id: u64,
+}
+
 /// A not so accurate but performant time and capacity based cache.
-pub struct Cache<K, V> {
-    map: DashMap<K, (V, Instant), ahash::RandomState>,
-    ringbuf: ArrayQueue<(K, Instant)>,
+///
+/// Keys are hashed with `S`, [`ahash`]'s `RandomState` by default, which can be
+/// changed with [`CacheBuilder::hasher`].
+pub struct Cache<K, V, S = ahash::RandomState> {
+    map: DashMap<K, Entry<V>, S>,
+    ringbuf: Ringbuf<Slot>,
+    // Keys whose value is being computed by `get_or_insert_with`.
+    loading: DashMap<K, Arc<Mutex<()>>, S>,
+    // Keys whose value is being loaded by `get_or_load`.
+    #[cfg(feature = "async")]
+    loading_async: DashMap<K, Arc<async_lock::Mutex<()>>, S>,
+    // Keys being refreshed in the background by `get_with_refresh`.
+    refreshing: DashSet<K, S>,
+    // Keys being loaded in the background by `get_fresh_or_stale`.
+    fresh_loads: DashMap<K, Arc<FreshLoad<V>>, S>,
+    stats: Counters,
+    trace: Tracer,
+    listener: Option<EvictionListener<K, V>>,
+    subscribers: Subscribers<K>,
+    weigher: Option<Weigher<K, V>>,
+    mem_sizer: Option<MemSizer<K, V>>,
+    expiry: Option<Arc<dyn Expiry<K, V>>>,
 
     capacity: usize,
+    max_weight: Option<u64>,
+    // The total weight shared with the other caches of a `CapacityPool`.
+    pool: Option<Arc<Pool>>,
     ttl: Duration,
+    // How much shorter than the TTL the expiration of an entry may be.
+    ttl_jitter: Option<Duration>,
+    tti: Option<Duration>,
+    // How long expired entries are kept and returned as stale.
+    stale_ttl: Option<Duration>,
+    // The slots of new entries not pushed to the ringbuf yet.
+    write_buffer: Option<WriteBuffer<Slot>>,
+    // Whether lookups sweep expired entries.
+    expire_on_read: bool,
+    // The ttl of `insert_negative`.
+    negative_ttl: Option<Duration>,
+    // The ttl of the errors cached by `get_or_try_insert_with`, if they're cached.
+    error_ttl: Option<Duration>,
+    // Refresh-ahead, for `get_or_refresh`.
+    refresh_after: Option<Duration>,
+    refresher: Option<Refresher<K, V>>,
+    // Runs the background loads and refreshes.
+    spawner: Spawner,
+    policy: EvictionPolicy,
+    // Only set with the TinyLFU admission policy.
+    sketch: Option<FrequencySketch>,
 
+    next_id: CachePadded<AtomicU64>,
+    // The total weight of the entries in the map.
+    weight: CachePadded<AtomicU64>,
+    // The number of entries in the map.
+    entries: CachePadded<AtomicUsize>,
+    // The total heap memory used by the entries in the map.
+    mem_size: CachePadded<AtomicUsize>,
+    // Entries with an id below this one have been cleared.
+    cleared: CachePadded<AtomicU64>,
+    // The epoch of the last `invalidate_before`.
+    epoch: AtomicU64,
     expire_started: CachePadded<AtomicBool>,
     oldest: CachePadded<AtomicCell<Instant>>,
+    created: Instant,
+    // Only set when the system clock isn't used, to avoid the dynamic dispatch.
+    clock: Option<Arc<dyn Clock>>,
+    name: Option<String>,
 }
 
 impl<K, V> Cache<K, V>
 where
     K: Eq + Hash + Clone,
-    V: Clone,
 {
     /// Create a new cache with the given capacity and time-to-live (TTL) for values.
+    ///
+    /// A capacity of zero or a zero TTL disables the cache, see
+    /// [`CacheBuilder`] for details.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid, see [`try_new`](Self::try_new).
     pub fn new(capacity: usize, ttl: Duration) -> Self {
-        Self {
-            map: DashMap::with_capacity_and_hasher(capacity, ahash::RandomState::new()),
-            ringbuf: ArrayQueue::new(capacity),
-            capacity,
-            ttl,
-            expire_started: CachePadded::new(AtomicBool::new(false)),
-            oldest: CachePadded::new(AtomicCell::new(Instant::now())),
-        }
+        Self::builder().capacity(capacity).ttl(ttl).build()
+    }
+
+    /// Create a new cache like [`new`](Self::new), or return an error if the
+    /// capacity is too large to be allocated.
+    pub fn try_new(capacity: usize, ttl: Duration) -> Result<Self, CacheConfigError> {
+        Self::builder().capacity(capacity).ttl(ttl).try_build()
     }
 
-    /// Get the number of elements in the cache.
+    /// Create a builder to configure a new cache.
+    pub fn builder() -> CacheBuilder<K, V> {
+        CacheBuilder::new()
+    }
+}
+
+impl<K, V, S> Cache<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    /// Create a new cache with the given capacity and time-to-live (TTL) for values,
+    /// hashing its keys with `hasher`, see [`CacheBuilder::hasher`].
+    ///
+    /// E.g. the standard library's `RandomState` resists hash flooding by keys
+    /// controlled by an attacker, at the cost of slower hashing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid, see [`try_new`](Cache::try_new).
+    pub fn with_hasher(capacity: usize, ttl: Duration, hasher: S) -> Self {
+        CacheBuilder::new()
+            .capacity(capacity)
+            .ttl(ttl)
+            .hasher(hasher)
+            .build()
+    }
+
+    /// Get the number of entries in the cache.
+    ///
+    /// This is an exact count of the entries in the map, kept up to date by every
+    /// insert and removal, so it's cheap to call concurrently. Expired entries count
+    /// until they're removed, either by a sweep or by
+    /// [`evict_expired`](Self::evict_expired).
     pub fn len(&self) -> usize {
-        self.ringbuf.len()
+        self.entries.load(Ordering::Acquire)
+    }
+
+    /// Get the number of slots in the eviction queue, which is what the capacity
+    /// bounds.
+    ///
+    /// Every new entry takes a slot, but a removed or replaced entry only gives its
+    /// slot back once it reaches the head of the queue, so this may be larger than
+    /// [`len`](Self::len). The slots buffered by a
+    /// [write buffer](CacheBuilder::write_buffer) are counted as well.
+    pub fn queue_len(&self) -> usize {
+        self.ringbuf.len() + self.write_buffer.as_ref().map_or(0, WriteBuffer::len)
+    }
+
+    /// Check if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
     /// Get the capacity of the cache.
@@ -99,136 +488,1873 @@ where
         self.capacity
     }
 
-    /// Get the value associated with the given key, if it exists and is not expired.
-    pub fn get(&self, key: &K) -> Option<Value<V>> {
-        let v = self.map.get(&key);
-        if v.is_none() {
+    /// Check if the cache is disabled because its capacity or its TTL is zero, in
+    /// which case it never stores anything.
+    pub fn is_disabled(&self) -> bool {
+        self.capacity == 0 || self.ttl.is_zero()
+    }
+
+    /// Get the name of the cache, if it was given one with [`CacheBuilder::name`].
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Get the total weight of the entries in the cache.
+    ///
+    /// Every entry weighs 1 unless a weigher is set with
+    /// [`CacheBuilder::weigher`].
+    pub fn weight(&self) -> u64 {
+        self.weight.load(Ordering::Acquire)
+    }
+
+    /// Estimate the memory used by the cache, in bytes.
+    ///
+    /// This counts the memory allocated for the map and the ringbuf, and the heap
+    /// memory used by the keys and values as reported by the function set with
+    /// [`CacheBuilder::mem_size`], if any. It's only an estimate: allocator overhead
+    /// and the internals of the map aren't accounted for.
+    pub fn estimated_memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.queue_len() * std::mem::size_of::<Slot>()
+            + self.map.capacity() * std::mem::size_of::<(K, Entry<V>)>()
+            + self.mem_size.load(Ordering::Acquire)
+    }
+
+    /// Get the maximum total weight of the entries in the cache, if any.
+    pub fn max_weight(&self) -> Option<u64> {
+        self.max_weight
+    }
+
+    /// Call `f` with a reference to the value associated with the given key, and
+    /// return its result.
+    ///
+    /// Unlike [`get`](Self::get) this doesn't need to clone the value, so it also
+    /// works for values which aren't `Clone`. `f` is called while holding a read lock
+    /// on the shard of the entry, so it must not write to the cache. Use
+    /// [`get_ref`](Self::get_ref) to also check if the value is expired.
+    pub fn with<R, Q>(&self, key: &Q, f: impl FnOnce(&V) -> R) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get_ref(key).map(|v| f(v.get()))
+    }
+
+    /// Project the value associated with the given key with `f`, and return the
+    /// result along with the expiration metadata of the entry.
+    ///
+    /// This is a lookup like [`get`](Self::get), but only the projection leaves the
+    /// cache, so for large values of which only a field is needed it saves the clone
+    /// of the whole value. As with [`with`](Self::with), `f` is called while holding
+    /// a read lock on the shard of the entry, so it must not write to the cache.
+    pub fn get_with<R, Q>(&self, key: &Q, f: impl FnOnce(&V) -> R) -> Option<(R, EntryMeta)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let v = self.get_ref(key)?;
+        let meta = EntryMeta {
+            expire_at: v.expire_at,
+            is_expired: v.is_expired,
+            inserted_at: v.entry.inserted_at,
+            read_at: v.read_at,
+            version: v.entry.version,
+        };
+        Some((f(v.get()), meta))
+    }
+
+    /// Get a reference to the value associated with the given key, without cloning it.
+    ///
+    /// This is useful when values are expensive to clone; storing them as `Arc<V>`
+    /// makes [`get`](Self::get) cheap as well. See [`ValueRef`] for the caveats of
+    /// holding the reference.
+    pub fn get_ref<Q>(&self, key: &Q) -> Option<ValueRef<'_, K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.now();
+        self.record_lookup(key);
+        // Expire first, as it can't be done while the reference is held.
+        self.expire_on_read(now);
+        let Some(entry) = self.lookup_entry(key, now) else {
+            self.stats.record(Counter::Miss);
             return None;
-        }
-        let v = v.unwrap();
-        let now = Instant::now();
-        let value = Value {
-            value: v.0.clone(),
-            expire_at: v.1,
-            is_expired: now > v.1,
         };
-        // The reference must be dropped, or will cause a deadlock when do expire.
-        drop(v);
+        let expire_at = self.read_entry(entry.key(), &entry, now);
+        let is_expired = now > expire_at;
+        if is_expired {
+            self.stats.record(Counter::StaleHit);
+        } else {
+            self.stats.record(Counter::Hit);
+        }
+        Some(ValueRef {
+            entry,
+            expire_at,
+            is_expired,
+            read_at: now,
+        })
+    }
+
+    /// Call `f` with a mutable reference to the value associated with the given key,
+    /// and return its result.
+    ///
+    /// This updates the value in place, without the clone of a `get` followed by an
+    /// `insert`, and keeps the position of the entry in the queue as well as its ttl.
+    /// It counts as a read of the entry, and the entry is weighed again afterwards.
+    /// `f` is called while holding a write lock on the shard of the entry, so it must
+    /// not access the cache.
+    pub fn modify<R, Q>(&self, key: &Q, f: impl FnOnce(&mut V) -> R) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.now();
+        // Expire first, as it can't be done while the entry is locked.
         self.do_expire(now);
-        Some(value)
+        let mut guard = self.map.get_mut(key).filter(|e| !self.is_cleared(e.id))?;
+        self.read_entry(guard.key(), &guard, now);
+        let (key, entry) = guard.pair_mut();
+        let result = f(&mut entry.value);
+        let sizes = self.measure(key, &entry.value);
+        self.on_modify(entry, sizes);
+        self.subscribers.emit(CacheEventKind::Update, key);
+        drop(guard);
+        self.evict_overweight(None);
+        Some(result)
+    }
+
+    /// Restart the ttl of the entry associated with the given key, without changing
+    /// its value, and return whether it was found.
+    ///
+    /// The entry then expires after the cache-wide ttl, even if it was inserted with
+    /// another one. An expired entry isn't brought back to life, and `false` is
+    /// returned instead.
+    pub fn touch<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.set_expire_at(key, |now, _| self.ttl_deadline(key, now))
+    }
+
+    /// Push the expiration of the entry associated with the given key back by
+    /// `extra`, without changing its value, and return whether it was found.
+    ///
+    /// As with [`touch`](Self::touch), an expired entry isn't brought back to life.
+    /// The entry never expires more than a century from now, as with
+    /// [`insert_with_ttl`](Self::insert_with_ttl).
+    pub fn extend_ttl<Q>(&self, key: &Q, extra: Duration) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.set_expire_at(key, |now, expire_at| {
+            let max = expiry::expire_after(now, Duration::MAX);
+            expire_at.checked_add(extra).map_or(max, |d| d.min(max))
+        })
+    }
+
+    /// Change the expiration of an entry which isn't expired, and count it as a read.
+    fn set_expire_at<Q>(&self, key: &Q, f: impl FnOnce(Instant, Instant) -> Instant) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.now();
+        let Some(mut e) = self.map.get_mut(key).filter(|e| !self.is_cleared(e.id)) else {
+            return false;
+        };
+        if now > self.deadline(&e) {
+            return false;
+        }
+        self.read_entry(e.key(), &e, now);
+        e.expire_at = f(now, self.expire_at(&e));
+        e.read_expire_at.store(u64::MAX, Ordering::Relaxed);
+        true
+    }
+
+    /// Get how long the value associated with the given key has left to live, which
+    /// is zero if it's expired.
+    ///
+    /// This is computed with the clock of the cache, and like
+    /// [`contains_key`](Self::contains_key) it doesn't have any side effect.
+    pub fn ttl_remaining<Q>(&self, key: &Q) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.now();
+        self.live_entry(key)
+            .map(|e| self.deadline(&e).saturating_duration_since(now))
+    }
+
+    /// Check if the cache contains the given key.
+    ///
+    /// Expired entries which haven't been removed yet are still reported. This
+    /// doesn't do expiration or record anything in the stats.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.live_entry(key).is_some()
+    }
+
+    /// Iterate over the keys in the cache, in no particular order.
+    ///
+    /// Keys of expired entries which haven't been removed yet are included. The
+    /// iterator holds a read lock on the shard it's currently visiting, so writing to
+    /// the cache from the same thread while iterating may deadlock.
+    pub fn keys(&self) -> impl Iterator<Item = K> + '_ {
+        self.map
+            .iter()
+            .filter(|e| !self.is_cleared(e.id))
+            .map(|e| e.key().clone())
     }
 
     /// Insert a key-value pair in the cache.
     ///
-    /// If the cache is full, it will evict the oldest entry.
+    /// If the cache is full, it will evict the oldest entry. If the key already
+    /// exists, its value is updated in place and its ttl restarts, but it keeps its
+    /// position in the queue.
     pub fn insert(&self, key: K, value: V) {
-        let now = Instant::now();
-        let expire_at = now + self.ttl;
-        while let Err(_) = self.ringbuf.push((key.clone(), expire_at)) {
-            // ringbuf is full, pop one
-            let (k, e) = self.ringbuf.pop().unwrap();
-            self.map.remove(&k);
-            self.oldest.store(e);
-        }
-        self.map.insert(key, (value, expire_at));
+        let now = self.now();
+        let expire_at = self.ttl_deadline(&key, now);
+        self.insert_inner(key, value, now, expire_at);
+    }
+
+    /// Insert a key-value pair in the cache like [`insert`](Self::insert), and return
+    /// the previous value associated with the key, even if it was expired.
+    pub fn replace(&self, key: K, value: V) -> Option<V> {
+        let now = self.now();
+        let expire_at = self.ttl_deadline(&key, now);
+        self.insert_inner(key, value, now, expire_at).old
+    }
+
+    /// Insert a key-value pair in the cache which expires after the given `ttl`
+    /// instead of the cache-wide one.
+    ///
+    /// Entries are still evicted in insertion order when the cache is full. An entry
+    /// with a short `ttl` queued behind entries with a longer one is reported as
+    /// expired by `get` once its own `ttl` has passed, but is only removed when it
+    /// reaches the head of the queue. A `ttl` of more than a century is cut down to
+    /// one, so `Duration::MAX` can be used for entries which never expire.
+    pub fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) {
+        let now = self.now();
+        self.insert_inner(key, value, now, expiry::expire_after(now, ttl));
+    }
+
+    /// Insert a key-value pair in the cache with the given [`Priority`].
+    ///
+    /// A [pinned](Priority::Pinned) entry is skipped when evicting to make room, so
+    /// it's only removed once it expires or when it's invalidated. The priority
+    /// sticks to the entry when it's updated with [`insert`](Self::insert) or
+    /// [`modify`](Self::modify), until it's inserted again with another one.
+    pub fn insert_with_priority(&self, key: K, value: V, priority: Priority) {
+        let now = self.now();
+        let expire_at = self.ttl_deadline(&key, now);
+        self.insert_entry(key, value, now, expire_at, Some(priority), None);
         self.do_expire(now);
     }
 
-    pub fn remove(&self, key: &K) -> Option<V> {
-        self.map.remove(key).map(|v| v.1.0)
+    /// Insert a key-value pair in the cache which expires at the given instant
+    /// instead of after the cache-wide ttl, e.g. to follow a deadline given by the
+    /// source of the value.
+    ///
+    /// The instant is compared with the clock of the cache. An entry inserted with a
+    /// deadline that has already passed is expired right away. See
+    /// [`insert_with_ttl`](Self::insert_with_ttl) for how such entries are removed.
+    pub fn insert_expiring_at(&self, key: K, value: V, deadline: Instant) {
+        let now = self.now();
+        self.insert_inner(key, value, now, deadline);
     }
 
-    /// Check and evict expired items in the cache.
-    fn do_expire(&self, now: Instant) {
-        if self.oldest.load() > now {
-            // don't need to do expire
-            return;
-        }
+    /// Insert a key-value pair, and return the previous value and the version and
+    /// expiration of the new one.
+    fn insert_inner(&self, key: K, value: V, now: Instant, expire_at: Instant) -> Inserted<V> {
+        let inserted = self.insert_entry(key, value, now, expire_at, None, None);
+        self.do_expire(now);
+        inserted
+    }
 
-        // grab the lock, a simple singleflight implementation
-        if self
-            .expire_started
-            .compare_exchange_weak(false, true, Ordering::AcqRel, Ordering::Relaxed)
-            .is_err()
-        {
-            return;
+    /// Insert a key-value pair in the cache like [`insert`](Self::insert), and return
+    /// the entries evicted to make room for it, oldest first.
+    ///
+    /// This lets callers log or forward the evictions caused by their own inserts,
+    /// while the [eviction listener](CacheBuilder::eviction_listener) sees all of
+    /// them; it's still called for these entries. A value which isn't admitted is
+    /// returned as well. Expired entries swept by the insert aren't included.
+    pub fn insert_evicting(&self, key: K, value: V) -> Vec<(K, V)> {
+        let now = self.now();
+        let mut evicted = Vec::new();
+        let expire_at = self.ttl_deadline(&key, now);
+        self.insert_entry(key, value, now, expire_at, None, Some(&mut evicted));
+        self.do_expire(now);
+        evicted
+    }
+
+    /// Insert a key-value pair like `insert_inner`, without the expiration sweep.
+    ///
+    /// The entry is given the priority if there is one, before it can be evicted.
+    /// Otherwise an existing entry keeps its own, and a new one isn't pinned.
+    fn insert_entry(
+        &self,
+        key: K,
+        value: V,
+        now: Instant,
+        expire_at: Instant,
+        priority: Option<Priority>,
+        evicted: Sink<'_, K, V>,
+    ) -> Inserted<V> {
+        let weight = self.weigher.as_ref().map_or(1, |w| w(&key, &value));
+        if self.is_disabled() || self.is_too_heavy(weight) || !self.admit(&key, weight) {
+            // A rejected value is reported as if it was evicted right away.
+            self.notify(&key, &value, RemovalCause::Size);
+            if let Some(evicted) = evicted {
+                evicted.push((key, value));
+            }
+            return Inserted {
+                old: None,
+                version: 0,
+                expire_at,
+            };
         }
+        let value = match self.update(&key, value, weight, now, expire_at, priority, None) {
+            Ok((old, version, expire_at)) => {
+                self.on_update(&key, &old, evicted);
+                return Inserted {
+                    old: Some(old),
+                    version,
+                    expire_at,
+                };
+            }
+            Err(value) => value,
+        };
+        self.insert_new(key, value, weight, now, expire_at, priority, evicted)
+    }
 
-        while let Some((k, t)) = self.ringbuf.pop() {
-            self.map.remove(&k);
-            if now <= t {
-                // TODO: find a way to put it back, or peek it instead of pop.
-                self.oldest.store(t);
-                break;
+    /// Insert a new entry in the map and push its slot.
+    #[allow(clippy::too_many_arguments)]
+    fn insert_new(
+        &self,
+        key: K,
+        value: V,
+        weight: u32,
+        now: Instant,
+        expire_at: Instant,
+        priority: Option<Priority>,
+        evicted: Sink<'_, K, V>,
+    ) -> Inserted<V> {
+        let entry = self.new_entry(&key, value, weight, now, expire_at, priority);
+        let (id, expire_at) = (entry.id, entry.expire_at);
+        let hash = self.hash_key(&key);
+        // Insert before pushing the slot, so it can't be popped before its entry exists.
+        // The key may have been inserted concurrently since `update`, in which case
+        // the slot of that entry is left behind and skipped lazily. The key is only
+        // cloned when it's needed once the shard is unlocked.
+        let (key, old) = match self.map.entry(key) {
+            MapEntry::Occupied(mut o) => (Some(o.key().clone()), Some(o.insert(entry))),
+            MapEntry::Vacant(v) => {
+                let key = self.subscribers.is_active().then(|| v.key().clone());
+                v.insert(entry);
+                (key, None)
             }
+        };
+        let old = old.and_then(|old| {
+            // The key is always cloned when there was an entry.
+            self.on_removal(key.as_ref().unwrap(), &old, RemovalCause::Replaced);
+            (!self.is_cleared(old.id)).then_some(old.value)
+        });
+        if let (None, Some(key)) = (&old, &key) {
+            self.subscribers.emit(CacheEventKind::Insert, key);
+        }
+        self.stats.record(Counter::Insertion);
+        self.push_slot(Slot { hash, id }, evicted);
+        Inserted {
+            old,
+            version: id,
+            expire_at,
         }
-        self.expire_started.store(false, Ordering::Release);
     }
 
-    pub fn clear(&self) {
-        while let Some((k, _)) = self.ringbuf.pop() {
-            self.map.remove(&k);
+    /// Insert a key-value pair in the cache like [`insert`](Self::insert), unless
+    /// that would evict an entry which isn't expired.
+    ///
+    /// Expired entries at the head of the queue are dropped to make room, but the
+    /// value is given back if the oldest entry is still alive. An existing key is
+    /// always updated, unless its new weight doesn't fit. This is best effort: a
+    /// concurrent insert may still cause an eviction.
+    pub fn try_insert(&self, key: K, value: V) -> Result<(), CacheFull<K, V>> {
+        let now = self.now();
+        let expire_at = self.ttl_deadline(&key, now);
+        let result = self.try_insert_entry(key, value, now, expire_at);
+        self.do_expire(now);
+        result
+    }
+
+    fn try_insert_entry(
+        &self,
+        key: K,
+        value: V,
+        now: Instant,
+        expire_at: Instant,
+    ) -> Result<(), CacheFull<K, V>> {
+        if self.is_disabled() {
+            return Err(CacheFull { key, value });
         }
-        self.oldest.store(Instant::now());
+        let weight = self.weigher.as_ref().map_or(1, |w| w(&key, &value));
+        let old_weight = self.live_entry(&key).map(|e| e.weight);
+        let value = match old_weight {
+            Some(old) if weight > old && self.is_overweight(weight - old) => {
+                return Err(CacheFull { key, value });
+            }
+            Some(_) => match self.update(&key, value, weight, now, expire_at, None, None) {
+                Ok((old, ..)) => {
+                    self.on_update(&key, &old, None);
+                    return Ok(());
+                }
+                // It was removed meanwhile.
+                Err(value) => value,
+            },
+            None => value,
+        };
+        if self.is_too_heavy(weight) || !self.make_room(weight, now) || !self.admit(&key, weight) {
+            return Err(CacheFull { key, value });
+        }
+        self.insert_new(key, value, weight, now, expire_at, None, None);
+        Ok(())
     }
-}
 
-/// A capacity based fifo cache.
-pub struct SizedCache<K, V> {
-    map: DashMap<K, V, ahash::RandomState>,
-    ringbuf: ArrayQueue<K>,
+    /// Insert a key-value pair only if the current value associated with the key has
+    /// the given [version](Value::version), and return the version of the new value.
+    ///
+    /// This lets concurrent writers refresh an entry without overwriting a fresher
+    /// value written meanwhile. The value is given back if the key doesn't exist, was
+    /// written since, or if the value is heavier than the maximum weight. The entry
+    /// is updated as with [`insert`](Self::insert) otherwise.
+    pub fn compare_and_insert(&self, key: K, value: V, expected: u64) -> Result<u64, V> {
+        let now = self.now();
+        let weight = self.weigher.as_ref().map_or(1, |w| w(&key, &value));
+        if self.is_too_heavy(weight) {
+            return Err(value);
+        }
+        let expire_at = self.ttl_deadline(&key, now);
+        let result = self
+            .update(&key, value, weight, now, expire_at, None, Some(expected))
+            .map(|(old, version, _)| {
+                self.on_update(&key, &old, None);
+                version
+            });
+        self.do_expire(now);
+        result
+    }
 
-    capacity: usize,
-}
+    /// Create an entry to be inserted in the map, with the next slot id and the
+    /// expiration given by the [`Expiry`] if there is one. It's only pinned with the
+    /// pinned priority.
+    fn new_entry(
+        &self,
+        key: &K,
+        value: V,
+        weight: u32,
+        now: Instant,
+        expire_at: Instant,
+        priority: Option<Priority>,
+    ) -> Entry<V> {
+        // Add the weight first, so it can't be subtracted before it's added.
+        self.weight.fetch_add(u64::from(weight), Ordering::AcqRel);
+        self.entries.fetch_add(1, Ordering::AcqRel);
+        if let Some(pool) = &self.pool {
+            pool.add(u64::from(weight));
+        }
+        let mem_size = self.mem_size_of(key, &value);
+        self.mem_size.fetch_add(mem_size, Ordering::AcqRel);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let expire_at = self
+            .expiry
+            .as_ref()
+            .and_then(|expiry| expiry.expire_after_create(key, &value, now))
+            .map_or(expire_at, |d| expiry::expire_after(now, d));
+        Entry {
+            value,
+            expire_at,
+            inserted_at: now,
+            id,
+            version: id,
+            visited: AtomicBool::new(false),
+            pinned: AtomicBool::new(priority == Some(Priority::Pinned)),
+            accessed: AtomicU64::new(self.nanos_since_created(now)),
+            read_expire_at: AtomicU64::new(u64::MAX),
+            weight,
+            mem_size,
+        }
+    }
 
-impl<K, V> SizedCache<K, V>
-where
-    K: Eq + Hash + Clone,
-    V: Clone,
-{
-    /// Create a new cache with the given capacity.
-    pub fn new(capacity: usize) -> Self {
-        Self {
-            map: DashMap::with_capacity_and_hasher(capacity, ahash::RandomState::new()),
-            ringbuf: ArrayQueue::new(capacity),
-            capacity,
+    /// Push the slot of an entry inserted in the map, evicting others to make room.
+    fn push_slot(&self, slot: Slot, mut evicted: Sink<'_, K, V>) {
+        match &self.write_buffer {
+            // The evicted entries wouldn't be known until the batch is flushed.
+            Some(buffer) if evicted.is_none() => match buffer.push(slot) {
+                Some(batch) => {
+                    for slot in batch {
+                        self.push_to_ringbuf(slot, None);
+                    }
+                }
+                // Buffered entries already count in the weight, but they can only be
+                // evicted once they're queued.
+                None if self.max_weight.is_some_and(|max| self.weight() > max) => {
+                    for slot in buffer.drain() {
+                        self.push_to_ringbuf(slot, None);
+                    }
+                }
+                None => return,
+            },
+            _ => self.push_to_ringbuf(slot, evicted.as_deref_mut()),
         }
+        self.evict_overweight(evicted);
+        self.stats.record_len(self.len());
     }
 
-    /// Get the number of elements in the cache.
-    pub fn len(&self) -> usize {
-        self.ringbuf.len()
+    /// Push a slot to the ringbuf, evicting the oldest entries while it's full.
+    fn push_to_ringbuf(&self, mut slot: Slot, mut evicted: Sink<'_, K, V>) {
+        let (mut kept, mut popped) = (0, 0);
+        let mut traced = None;
+        while let Err(s) = self.ringbuf.push(slot) {
+            // ringbuf is full, pop one
+            slot = s;
+            traced.get_or_insert_with(|| self.trace.evict_started(Pressure::Capacity));
+            if let Some(old) = self.ringbuf.pop() {
+                popped += 1;
+                // Don't go round forever if everything is pinned.
+                if self.evict(old, kept >= self.capacity, evicted.as_deref_mut()) {
+                    kept += 1;
+                }
+            }
+        }
+        if let Some(traced) = traced {
+            self.trace.evict_done(traced, popped);
+        }
     }
 
-    /// Get the capacity of the cache.
-    pub fn capacity(&self) -> usize {
-        self.capacity
+    /// Push the slots of the buffered inserts to the ringbuf, evicting entries if the
+    /// cache is full, see [`CacheBuilder::write_buffer`].
+    ///
+    /// This is done anyway each time a thread has buffered a full batch.
+    pub fn flush_writes(&self) {
+        let Some(buffer) = &self.write_buffer else {
+            return;
+        };
+        for slot in buffer.drain() {
+            self.push_to_ringbuf(slot, None);
+        }
+        self.evict_overweight(None);
+        self.stats.record_len(self.len());
     }
 
-    /// Get the value associated with the given key.
-    pub fn get(&self, key: &K) -> Option<V> {
-        self.map.get(&key).map(|v| v.value().clone())
+    /// Insert many key-value pairs in the cache, as with [`insert`](Self::insert).
+    ///
+    /// This only reads the clock and sweeps expired entries once for the whole batch.
+    pub fn insert_many(&self, entries: impl IntoIterator<Item = (K, V)>) {
+        let now = self.now();
+        for (key, value) in entries {
+            let expire_at = self.ttl_deadline(&key, now);
+            self.insert_entry(key, value, now, expire_at, None, None);
+        }
+        self.do_expire(now);
     }
 
-    /// Insert a key-value pair in the cache.
+    /// Insert many key-value pairs in the cache like
+    /// [`insert_many`](Self::insert_many), e.g. to warm it up on startup, with their
+    /// expirations spread over the last `jitter` of the TTL.
     ///
-    /// If the cache is full, it will evict the oldest entry.
-    pub fn insert(&self, key: K, value: V) {
-        while let Err(_) = self.ringbuf.push(key.clone()) {
-            // ringbuf is full, pop one
-            let k = self.ringbuf.pop().unwrap();
-            self.map.remove(&k);
+    /// Entries loaded together would otherwise all expire at once, and be reloaded
+    /// from the backend at the same time. Each entry expires between `ttl - jitter`
+    /// and `ttl` after it's inserted, at a point chosen at random, so no entry
+    /// outlives the TTL. A `jitter` longer than the TTL spreads them over the whole
+    /// TTL, and it takes precedence over [`CacheBuilder::ttl_jitter`]. As with
+    /// [`insert_with_ttl`](Self::insert_with_ttl), an entry is only removed once it
+    /// reaches the head of the queue.
+    pub fn warm(&self, entries: impl IntoIterator<Item = (K, V)>, jitter: Duration) {
+        let now = self.now();
+        for (key, value) in entries {
+            let expire_at = self.jittered_deadline(&key, now, jitter);
+            self.insert_entry(key, value, now, expire_at, None, None);
         }
-        self.map.insert(key, value);
+        self.do_expire(now);
     }
 
-    pub fn remove(&self, key: &K) -> Option<V> {
-        self.map.remove(key).map(|v| v.1)
+    /// Update the value of an existing entry in place, keeping its slot, and return
+    /// the previous value, the new version and the new expiration. The value is given
+    /// back if there is no such entry, or if its version isn't the `expected` one.
+    #[allow(clippy::too_many_arguments)]
+    fn update(
+        &self,
+        key: &K,
+        value: V,
+        weight: u32,
+        now: Instant,
+        expire_at: Instant,
+        priority: Option<Priority>,
+        expected: Option<u64>,
+    ) -> Result<(V, u64, Instant), V> {
+        let Some(mut e) = self.map.get_mut(key).filter(|e| {
+            !self.is_cleared(e.id) && expected.is_none_or(|version| e.version == version)
+        }) else {
+            return Err(value);
+        };
+        Ok(self.update_entry(key, &mut e, value, weight, now, expire_at, priority))
     }
 
-    pub fn clear(&self) {
-        while let Some(k) = self.ringbuf.pop() {
-            self.map.remove(&k);
+    /// Update the value of an entry in the map like `update`, once it's been found.
+    /// It keeps its priority unless it's given another one.
+    #[allow(clippy::too_many_arguments)]
+    fn update_entry(
+        &self,
+        key: &K,
+        e: &mut Entry<V>,
+        value: V,
+        weight: u32,
+        now: Instant,
+        expire_at: Instant,
+        priority: Option<Priority>,
+    ) -> (V, u64, Instant) {
+        let expire_at = match &self.expiry {
+            Some(expiry) => {
+                let remaining = self.expire_at(e).saturating_duration_since(now);
+                expiry
+                    .expire_after_update(key, &value, now, remaining)
+                    .map_or(expire_at, |d| expiry::expire_after(now, d))
+            }
+            None => expire_at,
+        };
+        e.version = self.next_id.fetch_add(1, Ordering::Relaxed);
+        e.expire_at = expire_at;
+        if let Some(priority) = priority {
+            e.pinned
+                .store(priority == Priority::Pinned, Ordering::Relaxed);
+        }
+        e.read_expire_at.store(u64::MAX, Ordering::Relaxed);
+        e.inserted_at = now;
+        e.accessed
+            .store(self.nanos_since_created(now), Ordering::Relaxed);
+        self.set_weight(e, weight);
+        if self.mem_sizer.is_some() {
+            let mem_size = self.mem_size_of(key, &value);
+            self.set_mem_size(e, mem_size);
         }
+        (std::mem::replace(&mut e.value, value), e.version, expire_at)
+    }
+
+    /// Account for an entry updated in place, once its shard is unlocked.
+    fn on_update(&self, key: &K, old: &V, evicted: Sink<'_, K, V>) {
+        self.stats.record(Counter::Insertion);
+        self.notify(key, old, RemovalCause::Replaced);
+        self.evict_overweight(evicted);
+    }
+
+    /// Compute the weight and the heap memory of a modified value, if they're
+    /// tracked.
+    fn measure(&self, key: &K, value: &V) -> (Option<u32>, Option<usize>) {
+        (
+            self.weigher.as_ref().map(|w| w(key, value)),
+            self.mem_sizer.as_ref().map(|f| f(key, value)),
+        )
+    }
+
+    /// Account for the value of an entry modified in place, given its new `measure`.
+    fn on_modify(&self, entry: &mut Entry<V>, (weight, mem_size): (Option<u32>, Option<usize>)) {
+        entry.version = self.next_id.fetch_add(1, Ordering::Relaxed);
+        if let Some(weight) = weight {
+            self.set_weight(entry, weight);
+        }
+        if let Some(mem_size) = mem_size {
+            self.set_mem_size(entry, mem_size);
+        }
+    }
+
+    /// Get the heap memory used by an entry, according to the memory sizer.
+    fn mem_size_of(&self, key: &K, value: &V) -> usize {
+        self.mem_sizer.as_ref().map_or(0, |f| f(key, value))
+    }
+
+    /// Change the heap memory used by an entry in the map.
+    fn set_mem_size(&self, entry: &mut Entry<V>, mem_size: usize) {
+        let old = std::mem::replace(&mut entry.mem_size, mem_size);
+        self.mem_size.fetch_add(mem_size, Ordering::AcqRel);
+        self.mem_size.fetch_sub(old, Ordering::AcqRel);
+    }
+
+    /// Change the weight of an entry in the map.
+    fn set_weight(&self, entry: &mut Entry<V>, weight: u32) {
+        let old = std::mem::replace(&mut entry.weight, weight);
+        self.weight.fetch_add(u64::from(weight), Ordering::AcqRel);
+        self.weight.fetch_sub(u64::from(old), Ordering::AcqRel);
+        if let Some(pool) = &self.pool {
+            pool.add(u64::from(weight));
+            pool.sub(u64::from(old));
+        }
+    }
+
+    /// Evict as many entries as needed to fit in the maximum weight, and in the
+    /// total weight of the pool.
+    ///
+    /// Only the entries evicted from this cache to fit its own maximum are sent to
+    /// `evicted`, the pool may evict from any of its caches.
+    fn evict_overweight(&self, mut evicted: Sink<'_, K, V>) {
+        let (mut kept, mut popped) = (0, 0);
+        let mut traced = None;
+        while self.max_weight.is_some_and(|max| self.weight() > max) {
+            traced.get_or_insert_with(|| self.trace.evict_started(Pressure::Weight));
+            match self.ringbuf.pop() {
+                Some(old) => {
+                    popped += 1;
+                    if self.evict(old, kept >= self.capacity, evicted.as_deref_mut()) {
+                        kept += 1;
+                    }
+                }
+                None => break,
+            }
+        }
+        if let Some(traced) = traced {
+            self.trace.evict_done(traced, popped);
+        }
+        if let Some(pool) = &self.pool {
+            pool.shrink();
+        }
+    }
+
+    /// Get when the entry at the head of the queue was written, unless it's empty,
+    /// for the [`CapacityPool`].
+    fn oldest_written_at(&self) -> Option<Instant> {
+        loop {
+            let mut written_at = None;
+            let popped = self.ringbuf.pop_if(|slot| {
+                written_at = self.with_slot(slot, |e| e.inserted_at);
+                written_at.is_none()
+            });
+            // Slots left behind by removed entries are dropped, and the next one is
+            // looked at.
+            if popped.is_none() {
+                return written_at;
+            }
+        }
+    }
+
+    /// Evict the entry at the head of the queue, skipping it as usual if it's pinned
+    /// or was visited, and return whether one was evicted, for the
+    /// [`CapacityPool`].
+    fn evict_oldest(&self) -> bool {
+        let mut kept = 0;
+        while let Some(slot) = self.ringbuf.pop() {
+            let live = self.is_live(&slot);
+            if self.evict(slot, kept >= self.capacity, None) {
+                kept += 1;
+            } else if live {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Remove the entry associated with the given key and return its value.
+    ///
+    /// The ringbuf slot of the entry is released lazily: it's skipped without
+    /// evicting anything once it reaches the head of the queue.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (k, e) = self.map.remove(key)?;
+        self.on_removal(&k, &e, RemovalCause::Explicit);
+        if self.is_cleared(e.id) {
+            return None;
+        }
+        Some(e.value)
+    }
+
+    /// Remove the entries associated with the given keys and return their values, in
+    /// the same order, as with [`remove`](Self::remove).
+    pub fn remove_many<'a, Q>(&self, keys: impl IntoIterator<Item = &'a Q>) -> Vec<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized + 'a,
+    {
+        keys.into_iter().map(|key| self.remove(key)).collect()
+    }
+
+    /// Discard the entry associated with the given key.
+    pub fn invalidate<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.remove(key);
+    }
+
+    /// Discard all entries in the cache.
+    pub fn invalidate_all(&self) {
+        self.clear();
+    }
+
+    /// Discard all entries for which `pred` returns true, e.g. all the keys of a
+    /// deleted tenant, and return how many were removed.
+    ///
+    /// This scans the whole cache, and `pred` is called with a shard locked, so it
+    /// must not access the cache. The entries are removed as with
+    /// [`remove`](Self::remove), and their slots are skipped once they reach the head
+    /// of the queue. An entry written concurrently after `pred` was called with it
+    /// survives.
+    pub fn invalidate_where(&self, pred: impl Fn(&K, &V) -> bool) -> usize {
+        let matching: Vec<(K, u64)> = self
+            .map
+            .iter()
+            .filter(|e| !self.is_cleared(e.id) && pred(e.key(), &e.value))
+            .map(|e| (e.key().clone(), e.version))
+            .collect();
+        let mut removed = 0;
+        for (key, version) in matching {
+            if let Some((k, e)) = self.map.remove_if(&key, |_, e| e.version == version) {
+                self.on_removal(&k, &e, RemovalCause::Explicit);
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Keep only the entries for which `f` returns true, see
+    /// [`invalidate_where`](Self::invalidate_where).
+    pub fn retain(&self, f: impl Fn(&K, &V) -> bool) {
+        self.invalidate_where(|k, v| !f(k, v));
+    }
+
+    /// Get the entry associated with the given key, unless it was cleared.
+    fn live_entry<Q>(&self, key: &Q) -> Option<Ref<'_, K, Entry<V>>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(key).filter(|e| !self.is_cleared(e.id))
+    }
+
+    /// Get the entry associated with the given key for a lookup, and remove it if it's
+    /// past its stale window.
+    fn lookup_entry<Q>(&self, key: &Q, now: Instant) -> Option<Ref<'_, K, Entry<V>>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let entry = self.live_entry(key)?;
+        if !self.is_dead(&entry, now) {
+            return Some(entry);
+        }
+        drop(entry);
+        let removed = self
+            .map
+            .remove_if(key, |_, e| !self.is_cleared(e.id) && self.is_dead(e, now));
+        if let Some((k, e)) = removed {
+            self.on_removal(&k, &e, RemovalCause::Expired);
+        }
+        None
+    }
+
+    /// Check if a new entry for the given key should be admitted, according to the
+    /// admission policy.
+    fn admit(&self, key: &K, weight: u32) -> bool {
+        let Some(sketch) = &self.sketch else {
+            return true;
+        };
+        let hash = self.hash_key(key);
+        sketch.increment(hash);
+        let full = self.ringbuf.len() >= self.capacity || self.is_overweight(weight);
+        if !full || self.map.contains_key(key) {
+            return true;
+        }
+        // The cache is full, so compare with the victim at the head of the ringbuf,
+        // unless another thread is looking at it.
+        let mut admit = true;
+        self.ringbuf.try_pop_if(|victim| {
+            if self.is_live(victim) {
+                admit = sketch.frequency(hash) > sketch.frequency(victim.hash);
+            }
+            false
+        });
+        admit
+    }
+
+    /// Check if the total weight would exceed the maximum with `extra` more.
+    fn is_overweight(&self, extra: u32) -> bool {
+        self.max_weight
+            .is_some_and(|max| self.weight() + u64::from(extra) > max)
+            || self
+                .pool
+                .as_ref()
+                .is_some_and(|pool| pool.used() + u64::from(extra) > pool.max())
+    }
+
+    /// Check if an entry of the given weight is too heavy to ever fit in the cache.
+    fn is_too_heavy(&self, weight: u32) -> bool {
+        let weight = u64::from(weight);
+        self.max_weight.is_some_and(|max| weight > max)
+            || self.pool.as_ref().is_some_and(|pool| weight > pool.max())
+    }
+
+    /// Record a lookup of the given key for the admission policy.
+    fn record_lookup<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if let Some(sketch) = &self.sketch {
+            sketch.increment(self.map.hasher().hash_one(key));
+        }
+    }
+
+    /// Record a read of the given entry, and get the instant it expires at.
+    ///
+    /// Reading an expired entry doesn't count as an access, so it can't bring the
+    /// entry back to life.
+    fn read_entry(&self, key: &K, entry: &Entry<V>, now: Instant) -> Instant {
+        let expire_at = self.deadline(entry);
+        if now > expire_at {
+            return expire_at;
+        }
+        // Check first to avoid writing to the cache line when it's already set.
+        if self.policy == EvictionPolicy::Lru && !entry.visited.load(Ordering::Relaxed) {
+            entry.visited.store(true, Ordering::Relaxed);
+        }
+        let Some(expiry) = &self.expiry else {
+            return match self.tti {
+                Some(tti) => {
+                    entry
+                        .accessed
+                        .store(self.nanos_since_created(now), Ordering::Relaxed);
+                    self.expire_at(entry).min(expiry::expire_after(now, tti))
+                }
+                None => expire_at,
+            };
+        };
+        if self.tti.is_some() {
+            entry
+                .accessed
+                .store(self.nanos_since_created(now), Ordering::Relaxed);
+        }
+        let remaining = self.expire_at(entry).saturating_duration_since(now);
+        if let Some(d) = expiry.expire_after_read(key, &entry.value, now, remaining) {
+            let read_expire_at = self.nanos_since_created(expiry::expire_after(now, d));
+            entry
+                .read_expire_at
+                .store(read_expire_at, Ordering::Relaxed);
+        }
+        self.deadline(entry)
+    }
+
+    /// Get the instant the given entry expires at, which is the earliest of its ttl
+    /// and its time-to-idle.
+    fn deadline(&self, entry: &Entry<V>) -> Instant {
+        let expire_at = self.expire_at(entry);
+        match self.tti {
+            Some(tti) => {
+                let accessed = Duration::from_nanos(entry.accessed.load(Ordering::Relaxed));
+                expire_at.min(expiry::expire_after(self.created + accessed, tti))
+            }
+            None => expire_at,
+        }
+    }
+
+    /// Get the instant the given entry may be removed at, which is the end of its
+    /// stale window, or its deadline if the window would end too far in the future.
+    fn removal_deadline(&self, entry: &Entry<V>) -> Instant {
+        let deadline = self.deadline(entry);
+        match self.stale_ttl {
+            Some(stale_ttl) => deadline.checked_add(stale_ttl).unwrap_or(deadline),
+            None => deadline,
+        }
+    }
+
+    /// Check if the given entry is past its stale window, so it can't be returned
+    /// anymore. Without a stale ttl, expired entries are returned until they're
+    /// removed.
+    fn is_dead(&self, entry: &Entry<V>, now: Instant) -> bool {
+        self.stale_ttl.is_some() && now > self.removal_deadline(entry)
+    }
+
+    /// Get the instant the given entry expires at regardless of its time-to-idle,
+    /// which may have been changed by `Expiry::expire_after_read`.
+    fn expire_at(&self, entry: &Entry<V>) -> Instant {
+        match entry.read_expire_at.load(Ordering::Relaxed) {
+            u64::MAX => entry.expire_at,
+            nanos => self.created + Duration::from_nanos(nanos),
+        }
+    }
+
+    /// Get the current instant from the clock of the cache.
+    fn now(&self) -> Instant {
+        match &self.clock {
+            Some(clock) => clock.now(),
+            None => Instant::now(),
+        }
+    }
+
+    /// Get the expiration of an entry written now with the TTL of the cache, see
+    /// [`CacheBuilder::ttl_jitter`].
+    fn ttl_deadline<Q>(&self, key: &Q, now: Instant) -> Instant
+    where
+        Q: Hash + ?Sized,
+    {
+        match self.ttl_jitter {
+            Some(jitter) => self.jittered_deadline(key, now, jitter),
+            None => expiry::expire_after(now, self.ttl),
+        }
+    }
+
+    /// Get the expiration of an entry written now with the TTL of the cache, made
+    /// shorter by up to `jitter`, picked at random from the key and the time.
+    fn jittered_deadline<Q>(&self, key: &Q, now: Instant, jitter: Duration) -> Instant
+    where
+        Q: Hash + ?Sized,
+    {
+        let seed = self.map.hasher().hash_one(key) ^ self.nanos_since_created(now);
+        let ttl = self.ttl.min(expiry::CENTURY);
+        now + (ttl - jitter.min(ttl).mul_f64(refresh::jitter(seed)))
+    }
+
+    fn nanos_since_created(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.created).as_nanos() as u64
+    }
+
+    /// Evict the entry owned by a slot popped from the head of the ringbuf to make
+    /// room, unless the eviction policy or its priority keeps it, and return whether
+    /// it was kept. With `force`, a pinned entry is evicted as well.
+    fn evict(&self, mut slot: Slot, force: bool, evicted: Sink<'_, K, V>) -> bool {
+        let keep = self.with_slot(&slot, |e| {
+            (!force && e.pinned.load(Ordering::Relaxed))
+                || (self.policy == EvictionPolicy::Lru && e.visited.swap(false, Ordering::Relaxed))
+        }) == Some(true);
+        if keep {
+            // Give it a second chance, unless someone else took its place meanwhile.
+            match self.ringbuf.push(slot) {
+                Ok(()) => return true,
+                Err(s) => slot = s,
+            }
+        }
+        if let Some((k, e)) = self.remove_slot(&slot) {
+            self.on_removal(&k, &e, RemovalCause::Size);
+            if let Some(evicted) = evicted {
+                evicted.push((k, e.value));
+            }
+        }
+        false
+    }
+
+    /// Hash a key like the map does, to find its entry from a slot.
+    fn hash_key<Q: Hash + ?Sized>(&self, key: &Q) -> u64 {
+        self.map.hasher().hash_one(key)
+    }
+
+    /// Call `f` with the entry owned by the given slot if it's still in the map,
+    /// while holding a read lock on its shard, and return its result.
+    fn with_slot<R>(&self, slot: &Slot, f: impl FnOnce(&Entry<V>) -> R) -> Option<R> {
+        let shard = self.map.shards()[self.map.determine_shard(slot.hash as usize)].read();
+        let bucket = shard.find(slot.hash, |(_, e)| e.get().id == slot.id)?;
+        // SAFETY: the bucket was found in the shard, which stays locked while it's used.
+        let (_, e) = unsafe { bucket.as_ref() };
+        Some(f(e.get()))
+    }
+
+    /// Remove the entry owned by the given slot if it's still in the map and `f`
+    /// returns true for it.
+    fn remove_slot_if(
+        &self,
+        slot: &Slot,
+        f: impl FnOnce(&Entry<V>) -> bool,
+    ) -> Option<(K, Entry<V>)> {
+        let mut shard = self.map.shards()[self.map.determine_shard(slot.hash as usize)].write();
+        let bucket = shard.find(slot.hash, |(_, e)| e.get().id == slot.id)?;
+        // SAFETY: the bucket was found in the shard, which stays locked while it's used,
+        // and it's not used after being removed.
+        let ((k, e), _) = unsafe {
+            if !f(bucket.as_ref().1.get()) {
+                return None;
+            }
+            shard.remove(bucket)
+        };
+        Some((k, e.into_inner()))
+    }
+
+    /// Remove the entry owned by the given slot, if it's still in the map.
+    fn remove_slot(&self, slot: &Slot) -> Option<(K, Entry<V>)> {
+        self.remove_slot_if(slot, |_| true)
+    }
+
+    /// Account for the removal of an entry from the map.
+    fn on_removal(&self, key: &K, entry: &Entry<V>, cause: RemovalCause) {
+        self.release(entry);
+        // Entries inserted before a `clear` may only be removed after it returns.
+        let cause = if self.is_cleared(entry.id) {
+            RemovalCause::Explicit
+        } else {
+            cause
+        };
+        self.notify(key, &entry.value, cause);
+        self.stats.record_len(self.len());
+    }
+
+    /// Subtract the weight and memory of an entry removed from the map, and the entry
+    /// from the count.
+    fn release(&self, entry: &Entry<V>) {
+        self.weight
+            .fetch_sub(u64::from(entry.weight), Ordering::AcqRel);
+        self.entries.fetch_sub(1, Ordering::AcqRel);
+        if let Some(pool) = &self.pool {
+            pool.sub(u64::from(entry.weight));
+        }
+        self.mem_size.fetch_sub(entry.mem_size, Ordering::AcqRel);
+    }
+
+    /// Record the removal of a value and notify the eviction listener.
+    fn notify(&self, key: &K, value: &V, cause: RemovalCause) {
+        self.trace.removed(cause);
+        match cause {
+            RemovalCause::Expired => self.stats.record(Counter::Expiration),
+            RemovalCause::Size => self.stats.record(Counter::Eviction),
+            RemovalCause::Explicit | RemovalCause::Replaced => {}
+        }
+        if let Some(listener) = &self.listener {
+            listener(key, value, cause);
+        }
+        let kind = match cause {
+            RemovalCause::Expired => CacheEventKind::Expire,
+            RemovalCause::Size => CacheEventKind::Evict,
+            RemovalCause::Explicit => CacheEventKind::Remove,
+            RemovalCause::Replaced => CacheEventKind::Update,
+        };
+        self.subscribers.emit(kind, key);
+    }
+
+    /// Check if the entry owned by the given slot is still in the map.
+    fn is_live(&self, slot: &Slot) -> bool {
+        !self.is_cleared(slot.id) && self.with_slot(slot, |_| ()).is_some()
+    }
+
+    /// Check if the entry with the given id was inserted before the last `clear`.
+    fn is_cleared(&self, id: u64) -> bool {
+        id < self.cleared.load(Ordering::Acquire)
+    }
+
+    /// Remove expired entries from the head of the queue, like the sweep done by
+    /// lookups and writes, and return how many were removed.
+    ///
+    /// At most `max_items` slots are popped, including the ones left behind by
+    /// removed entries, so this can be called regularly from a low-priority task to
+    /// amortize expiration, e.g. with [`CacheBuilder::expire_on_read`] disabled.
+    /// Nothing is done if another thread is sweeping already, and the sweep stops
+    /// early if an insert is looking at the head of the queue meanwhile.
+    pub fn sweep_expired(&self, max_items: usize) -> usize {
+        self.sweep(self.now(), max_items)
+    }
+
+    /// Remove the entry at the head of the queue if it's expired, and return it with
+    /// the instant it expired at.
+    ///
+    /// The slots left behind by removed entries are skipped, but like the sweep this
+    /// stops at the first entry which is still alive, so call it in a loop to take
+    /// all the expired entries in front of it. The eviction listener is still called
+    /// with the entries returned.
+    pub fn pop_expired(&self) -> Option<(K, V, Instant)> {
+        let now = self.now();
+        loop {
+            let slot = self
+                .ringbuf
+                .pop_if(|slot| self.slot_deadline(slot).is_none_or(|d| now > d))?;
+            match self.expire_slot(slot, now) {
+                Some((k, e)) if !self.is_cleared(e.id) => {
+                    let expired_at = self.deadline(&e);
+                    return Some((k, e.value, expired_at));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Remove the expired entries from the head of the queue one by one, as with
+    /// [`pop_expired`](Self::pop_expired), and yield them instead of dropping them.
+    ///
+    /// This makes the cache usable as a staging buffer bounded in time: the entries
+    /// which aged past their TTL can be flushed to storage instead of being
+    /// discarded. The iterator is lazy and stops at the first entry which is still
+    /// alive.
+    ///
+    /// Writes still sweep the expired entries at the head of the queue, and so do
+    /// lookups unless [`CacheBuilder::expire_on_read`] is disabled. The entries swept
+    /// that way are only seen by the eviction listener, so either drain the cache
+    /// before writing to it, or flush from the listener as well.
+    pub fn drain_expired(&self) -> impl Iterator<Item = (K, V)> + '_ {
+        std::iter::from_fn(|| self.pop_expired().map(|(k, v, _)| (k, v)))
+    }
+
+    /// Remove the entry at the head of the queue, which is the oldest one, and return
+    /// it whether it's expired or not.
+    ///
+    /// The slots left behind by removed entries are skipped, and so is the priority
+    /// of the entries: pinned entries are popped as well. The eviction listener is
+    /// called with the entry as an explicit removal.
+    pub fn pop_oldest(&self) -> Option<(K, Value<V>)> {
+        let now = self.now();
+        loop {
+            let slot = match self.ringbuf.pop() {
+                Some(slot) => slot,
+                // The remaining entries may still be buffered.
+                None if self.write_buffer.is_some() => {
+                    self.flush_writes();
+                    self.ringbuf.pop()?
+                }
+                None => return None,
+            };
+            let Some((k, e)) = self.remove_slot(&slot) else {
+                continue;
+            };
+            self.on_removal(&k, &e, RemovalCause::Explicit);
+            if self.is_cleared(e.id) {
+                continue;
+            }
+            let expire_at = self.deadline(&e);
+            let value = Value {
+                value: e.value,
+                expire_at,
+                is_expired: now > expire_at,
+                inserted_at: e.inserted_at,
+                read_at: now,
+                version: e.version,
+            };
+            return Some((k, value));
+        }
+    }
+
+    /// Check and evict expired items in the cache.
+    fn do_expire(&self, now: Instant) {
+        self.sweep(now, usize::MAX);
+    }
+
+    /// Do the expiration sweep of a lookup, unless it's disabled.
+    fn expire_on_read(&self, now: Instant) {
+        if self.expire_on_read {
+            self.do_expire(now);
+        }
+    }
+
+    /// Pop up to `max` expired slots, and return the number of entries removed.
+    fn sweep(&self, now: Instant, max: usize) -> usize {
+        if self.oldest.load() > now {
+            // don't need to do expire
+            return 0;
+        }
+
+        // grab the lock, a simple singleflight implementation
+        if self
+            .expire_started
+            .compare_exchange_weak(false, true, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            return 0;
+        }
+
+        // Entries may be out of order when they have different ttls, so stop at the
+        // first one that is still alive and leave it at the head of the queue.
+        let traced = self.trace.sweep_started();
+        let mut removed = 0;
+        for _ in 0..max {
+            let popped = self
+                .ringbuf
+                .try_pop_if(|slot| match self.slot_deadline(slot) {
+                    Some(deadline) if now <= deadline => {
+                        self.oldest.store(deadline);
+                        false
+                    }
+                    _ => true,
+                });
+            let Some(slot) = popped else {
+                break;
+            };
+            if self.expire_slot(slot, now).is_some() {
+                removed += 1;
+            }
+        }
+        self.trace.sweep_done(traced, removed);
+        self.expire_started.store(false, Ordering::Release);
+        removed
+    }
+
+    /// Get the instant the entry owned by a slot expires at, unless it's gone.
+    fn slot_deadline(&self, slot: &Slot) -> Option<Instant> {
+        if self.is_cleared(slot.id) {
+            return None;
+        }
+        self.with_slot(slot, |e| self.removal_deadline(e))
+    }
+
+    /// Remove the entry owned by a slot popped because it was expired or gone, and
+    /// return it if there was one.
+    fn expire_slot(&self, slot: Slot, now: Instant) -> Option<(K, Entry<V>)> {
+        // Check again, as the entry may have been read since.
+        let removed = self.remove_slot_if(&slot, |e| {
+            self.is_cleared(e.id) || now > self.removal_deadline(e)
+        });
+        match removed {
+            Some((k, e)) => {
+                self.on_removal(&k, &e, RemovalCause::Expired);
+                return Some((k, e));
+            }
+            // It's alive again, so it must keep its slot or it could never be
+            // evicted nor expired.
+            None if self.is_live(&slot) => {
+                if let Err(slot) = self.ringbuf.push(slot) {
+                    self.evict(slot, true, None);
+                }
+            }
+            None => {}
+        }
+        None
+    }
+
+    /// Drop expired entries from the head of the queue until there is room for an
+    /// entry with the given weight, and return whether there is.
+    fn make_room(&self, weight: u32, now: Instant) -> bool {
+        while self.ringbuf.len() >= self.capacity || self.is_overweight(weight) {
+            let popped = self
+                .ringbuf
+                .try_pop_if(|slot| self.slot_deadline(slot).is_none_or(|d| now > d));
+            match popped {
+                Some(slot) => {
+                    self.expire_slot(slot, now);
+                }
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Remove all expired entries from the cache.
+    ///
+    /// Expired entries are otherwise only dropped as a side effect of reads and
+    /// writes, and only once they reach the head of the queue, so this is useful to
+    /// release memory held by a cache which sees little traffic. Unlike those sweeps
+    /// this walks the whole map. The entries invalidated by
+    /// [`invalidate_before`](Self::invalidate_before) are removed as well. See also
+    /// [`spawn_janitor`](Self::spawn_janitor).
+    pub fn evict_expired(&self) {
+        let now = self.now();
+        self.do_expire(now);
+        // Entries with a shorter ttl may still be stuck behind one that is alive.
+        let traced = self.trace.sweep_started();
+        let mut removed = 0;
+        let expired: Vec<K> = self
+            .map
+            .iter()
+            .filter(|e| self.is_cleared(e.id) || now > self.removal_deadline(e.value()))
+            .map(|e| e.key().clone())
+            .collect();
+        for key in expired {
+            let entry = self.map.remove_if(&key, |_, e| {
+                self.is_cleared(e.id) || now > self.removal_deadline(e)
+            });
+            if let Some((k, e)) = entry {
+                self.on_removal(&k, &e, RemovalCause::Expired);
+                removed += 1;
+            }
+        }
+        self.trace.sweep_done(traced, removed);
+    }
+
+    /// Get the current epoch of the cache, which entries written from now on belong
+    /// to, see [`invalidate_before`](Self::invalidate_before).
+    ///
+    /// The epoch starts at zero, and only moves forward.
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Acquire)
+    }
+
+    /// Invalidate every entry written in an epoch older than `epoch`, and move the
+    /// cache to `epoch` if it's newer than the current one. Return whether it was.
+    ///
+    /// This is meant for invalidation protocols spanning several caches, e.g. a
+    /// pub/sub message telling every node to flush what it cached before a version
+    /// of the data: the epoch is compared as a number, so the same message can be
+    /// applied twice or out of order. The entries are invalidated lazily, without
+    /// walking the map: lookups miss them right away, but they're only removed once
+    /// they reach the head of the queue, or by
+    /// [`evict_expired`](Self::evict_expired), and count in [`len`](Self::len) until
+    /// then. They're reported to the eviction listener as explicitly removed. As with
+    /// [`clear`](Self::clear), entries written concurrently may or may not survive.
+    pub fn invalidate_before(&self, epoch: u64) -> bool {
+        let mut current = self.epoch.load(Ordering::Acquire);
+        while current < epoch {
+            match self.epoch.compare_exchange_weak(
+                current,
+                epoch,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    // An epoch only ends when it's invalidated, so everything written
+                    // before is older than `epoch`.
+                    let cleared = self.next_id.load(Ordering::Acquire);
+                    self.cleared.fetch_max(cleared, Ordering::AcqRel);
+                    // Let the next sweep pop the invalidated slots.
+                    self.oldest.store(self.now());
+                    return true;
+                }
+                Err(e) => current = e,
+            }
+        }
+        // Entries of older epochs were invalidated when this one started.
+        false
+    }
+
+    /// Remove all entries in the cache.
+    ///
+    /// This is safe to call concurrently with other operations: every entry inserted
+    /// before the call is gone once it returns, even if its insertion was still in
+    /// flight, while entries inserted concurrently may survive.
+    pub fn clear(&self) {
+        // Ids are handed out in insertion order, so everything below the next one is
+        // cleared, including inserts that haven't reached the map yet.
+        let cleared = self.next_id.load(Ordering::Acquire);
+        self.cleared.fetch_max(cleared, Ordering::AcqRel);
+        if self.listener.is_none() && !self.subscribers.is_active() {
+            self.map.retain(|_, e| {
+                if !self.is_cleared(e.id) {
+                    return true;
+                }
+                self.release(e);
+                false
+            });
+        } else {
+            // Collect the keys first, so the listener and the subscribers aren't called
+            // with a shard locked.
+            let keys: Vec<K> = self
+                .map
+                .iter()
+                .filter(|e| self.is_cleared(e.id))
+                .map(|e| e.key().clone())
+                .collect();
+            for key in keys {
+                if let Some((k, e)) = self.map.remove_if(&key, |_, e| self.is_cleared(e.id)) {
+                    self.on_removal(&k, &e, RemovalCause::Explicit);
+                }
+            }
+        }
+        while self
+            .ringbuf
+            .pop_if(|slot| self.is_cleared(slot.id))
+            .is_some()
+        {}
+        self.oldest.store(self.now());
+        self.stats.record_len(self.len());
+    }
+}
+
+impl<K, V, S> Cache<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    /// Get the value associated with the given key, if it exists and is not expired.
+    pub fn get<Q>(&self, key: &Q) -> Option<Value<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.now();
+        let value = self.get_at(key, now);
+        self.expire_on_read(now);
+        value
+    }
+
+    /// Get the value associated with the given key like [`get`](Self::get), and
+    /// restart its ttl in the same step.
+    ///
+    /// This gives a sliding expiration to the lookups which ask for it, without a
+    /// time-to-idle for the whole cache. Unlike a `get` followed by a
+    /// [`touch`](Self::touch), both are done under the lock of the shard of the entry,
+    /// so the value returned is the one whose expiration was pushed back. An expired
+    /// value is returned as such, and isn't brought back to life.
+    pub fn get_and_refresh<Q>(&self, key: &Q) -> Option<Value<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get_refreshed(key, |now| self.ttl_deadline(key, now))
+    }
+
+    /// Get the value associated with the given key like
+    /// [`get_and_refresh`](Self::get_and_refresh), and make it expire after `ttl`
+    /// instead of the cache-wide ttl. As with
+    /// [`insert_with_ttl`](Self::insert_with_ttl), a `ttl` of more than a century is
+    /// cut down to one.
+    pub fn get_and_refresh_with_ttl<Q>(&self, key: &Q, ttl: Duration) -> Option<Value<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get_refreshed(key, |now| expiry::expire_after(now, ttl))
+    }
+
+    fn get_refreshed<Q>(
+        &self,
+        key: &Q,
+        deadline: impl FnOnce(Instant) -> Instant,
+    ) -> Option<Value<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.now();
+        self.record_lookup(key);
+        // Entries past their stale window are left to the sweep.
+        let value = self
+            .map
+            .get_mut(key)
+            .filter(|e| !self.is_cleared(e.id) && !self.is_dead(e, now))
+            .map(|mut e| {
+                let mut expire_at = self.read_entry(e.key(), &e, now);
+                if now <= expire_at {
+                    e.expire_at = deadline(now);
+                    e.read_expire_at.store(u64::MAX, Ordering::Relaxed);
+                    expire_at = self.deadline(&e);
+                }
+                e.to_value(expire_at, now)
+            });
+        match &value {
+            Some(v) if v.is_expired() => self.stats.record(Counter::StaleHit),
+            Some(_) => self.stats.record(Counter::Hit),
+            None => self.stats.record(Counter::Miss),
+        }
+        self.expire_on_read(now);
+        value
+    }
+
+    /// Get the values associated with the given keys, in the same order, as with
+    /// [`get`](Self::get).
+    ///
+    /// This only reads the clock and sweeps expired entries once for the whole batch.
+    pub fn get_many<'a, Q>(&self, keys: impl IntoIterator<Item = &'a Q>) -> Vec<Option<Value<V>>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized + 'a,
+    {
+        let now = self.now();
+        let values = keys.into_iter().map(|key| self.get_at(key, now)).collect();
+        self.expire_on_read(now);
+        values
+    }
+
+    /// Look up the given key like `get`, without the expiration sweep.
+    fn get_at<Q>(&self, key: &Q, now: Instant) -> Option<Value<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.record_lookup(key);
+        // The reference must be dropped before doing expire, or it will deadlock.
+        let value = self
+            .lookup_entry(key, now)
+            .map(|e| e.to_value(self.read_entry(e.key(), &e, now), now));
+        match &value {
+            Some(v) if v.is_expired() => self.stats.record(Counter::StaleHit),
+            Some(_) => self.stats.record(Counter::Hit),
+            None => self.stats.record(Counter::Miss),
+        }
+        value
+    }
+
+    /// Iterate over the entries in the cache, in no particular order.
+    ///
+    /// Like [`get`](Self::get), expired entries which haven't been removed yet are
+    /// included, and can be told apart with [`Value::is_expired`]. See
+    /// [`keys`](Self::keys) for the caveats of holding the iterator.
+    pub fn iter(&self) -> impl Iterator<Item = (K, Value<V>)> + '_ {
+        let now = self.now();
+        self.map
+            .iter()
+            .filter(move |e| !self.is_cleared(e.id) && !self.is_dead(e, now))
+            .map(move |e| (e.key().clone(), e.to_value(self.deadline(&e), now)))
+    }
+
+    /// Iterate over the values in the cache, in no particular order.
+    ///
+    /// See [`iter`](Self::iter) for details.
+    pub fn values(&self) -> impl Iterator<Item = Value<V>> + '_ {
+        self.iter().map(|(_, v)| v)
+    }
+
+    /// Collect all entries in the cache.
+    ///
+    /// See [`iter`](Self::iter) for details.
+    pub fn snapshot(&self) -> Vec<(K, Value<V>)> {
+        self.iter().collect()
+    }
+
+    /// Get the value associated with the given key like [`get`](Self::get), but
+    /// without side effects: this doesn't do expiration or record anything in the
+    /// stats.
+    pub fn peek<Q>(&self, key: &Q) -> Option<Value<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.now();
+        self.live_entry(key)
+            .filter(|e| !self.is_dead(e, now))
+            .map(|e| e.to_value(self.deadline(&e), now))
+    }
+
+    /// Get the value associated with the given key, or compute it with `f` and
+    /// insert it if it doesn't exist or is expired.
+    ///
+    /// Concurrent calls for the same key are deduplicated: only one of them runs
+    /// `f`, and the others wait for it and return the value it inserted.
+    pub fn get_or_insert_with(&self, key: K, f: impl FnOnce() -> V) -> Value<V> {
+        match self.try_get_or_insert_with(key, || Ok::<_, Infallible>(f())) {
+            Ok(value) => value,
+            Err(e) => match e {},
+        }
+    }
+
+    /// Like [`get_or_insert_with`](Self::get_or_insert_with), but `f` may fail.
+    ///
+    /// If `f` returns an error nothing is inserted and the error is returned; callers
+    /// waiting on the same key will then run their own `f`.
+    pub fn try_get_or_insert_with<E>(
+        &self,
+        key: K,
+        f: impl FnOnce() -> Result<V, E>,
+    ) -> Result<Value<V>, E> {
+        self.load(key, || f().map(|value| (value, None)))
+    }
+
+    /// Get the value associated with the given key like `try_get_or_insert_with`,
+    /// inserting the one loaded by `f` with its own ttl, if any.
+    fn load<E>(
+        &self,
+        key: K,
+        f: impl FnOnce() -> Result<(V, Option<Duration>), E>,
+    ) -> Result<Value<V>, E> {
+        if let Some(value) = self.get(&key).filter(|v| !v.is_expired()) {
+            return Ok(value);
+        }
+
+        let loading = Loading::new(&self.loading, &key);
+        let guard = loading.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        // Someone else may have inserted it while we were waiting.
+        let result = match self.peek(&key).filter(|v| !v.is_expired()) {
+            Some(value) => Ok(value),
+            None => f().map(|(value, ttl)| {
+                let now = self.now();
+                let expire_at = match ttl {
+                    Some(ttl) => expiry::expire_after(now, ttl),
+                    None => self.ttl_deadline(&key, now),
+                };
+                let inserted = self.insert_inner(key.clone(), value.clone(), now, expire_at);
+                Value::inserted(value, now, inserted.expire_at, inserted.version)
+            }),
+        };
+        drop(guard);
+        result
+    }
+}
+
+/// The lock of a key being loaded, which is removed from its map once the load is
+/// over, even if the loader panicked or was cancelled, so that the next caller can
+/// start another one.
+struct Loading<'a, K, L, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Clone,
+{
+    map: &'a DashMap<K, Arc<L>, S>,
+    key: &'a K,
+    lock: Arc<L>,
+}
+
+impl<'a, K, L, S> Loading<'a, K, L, S>
+where
+    K: Eq + Hash + Clone,
+    L: Default,
+    S: BuildHasher + Clone,
+{
+    /// Get the lock of the given key, or start loading it.
+    fn new(map: &'a DashMap<K, Arc<L>, S>, key: &'a K) -> Self {
+        let lock = map.entry(key.clone()).or_default().clone();
+        Self { map, key, lock }
+    }
+}
+
+impl<K, L, S> Drop for Loading<'_, K, L, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Clone,
+{
+    fn drop(&mut self) {
+        // A newer load of the key may have started already.
+        self.map
+            .remove_if(self.key, |_, l| Arc::ptr_eq(l, &self.lock));
+    }
+}
+
+impl<K, V, S> Drop for Cache<K, V, S> {
+    fn drop(&mut self) {
+        // The entries are dropped with the map, so they no longer count toward the
+        // total weight of the pool.
+        if let Some(pool) = &self.pool {
+            pool.sub(self.weight.load(Ordering::Acquire));
+        }
+    }
+}
+
+// The concurrent caches must stay shareable between threads, and `LocalCache` must
+// still be movable to another one.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    const fn assert_send<T: Send>() {}
+    assert_send_sync::<Cache<String, String>>();
+    assert_send_sync::<ShardedCache<String, String>>();
+    assert_send_sync::<SizedCache<String, String>>();
+    assert_send::<LocalCache<String, String>>();
+};
+
+/// A capacity based fifo cache.
+pub struct SizedCache<K, V> {
+    // Values are stored with the id of the ringbuf slot which owns them.
+    map: DashMap<K, (V, u64), ahash::RandomState>,
+    ringbuf: ArrayQueue<(K, u64)>,
+
+    capacity: usize,
+    next_id: CachePadded<AtomicU64>,
+}
+
+impl<K, V> SizedCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    /// Create a new cache with the given capacity.
+    ///
+    /// A cache with a capacity of zero never stores anything.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            map: DashMap::with_capacity_and_hasher(capacity, ahash::RandomState::new()),
+            // `ArrayQueue` can't be empty, inserts are skipped instead.
+            ringbuf: ArrayQueue::new(capacity.max(1)),
+            capacity,
+            next_id: CachePadded::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Get the number of entries in the cache.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Get the number of slots in the eviction queue, including the ones left behind
+    /// by removed or replaced entries, see [`Cache::queue_len`].
+    pub fn queue_len(&self) -> usize {
+        self.ringbuf.len()
+    }
+
+    /// Check if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the capacity of the cache.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Insert a key-value pair in the cache.
+    ///
+    /// If the cache is full, it will evict the oldest entry.
+    pub fn insert(&self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.map.insert(key.clone(), (value, id));
+        let mut slot = (key, id);
+        while let Err(s) = self.ringbuf.push(slot) {
+            // ringbuf is full, pop one
+            slot = s;
+            if let Some((k, id)) = self.ringbuf.pop() {
+                self.map.remove_if(&k, |_, v| v.1 == id);
+            }
+        }
+    }
+
+    /// Remove the entry associated with the given key and return its value.
+    ///
+    /// The ringbuf slot of the entry is released lazily: it's skipped without
+    /// evicting anything once it reaches the head of the queue.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove(key).map(|(_, v)| v.0)
+    }
+
+    /// Discard the entry associated with the given key.
+    pub fn invalidate<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove(key);
+    }
+
+    /// Discard all entries in the cache.
+    pub fn invalidate_all(&self) {
+        self.clear();
+    }
+
+    pub fn clear(&self) {
+        while let Some((k, id)) = self.ringbuf.pop() {
+            self.map.remove_if(&k, |_, v| v.1 == id);
+        }
+    }
+
+    /// Check if the cache contains the given key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Call `f` with a reference to the value associated with the given key, and
+    /// return its result.
+    pub fn with<R, Q>(&self, key: &Q, f: impl FnOnce(&V) -> R) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(key).map(|v| f(&v.0))
+    }
+}
+
+impl<K, V> SizedCache<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    /// Get the value associated with the given key.
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(key).map(|v| v.0.clone())
     }
 }
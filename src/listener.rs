@@ -0,0 +1,17 @@
+use std::sync::Arc;
+
+/// The reason an entry was removed from a [`Cache`](crate::Cache).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RemovalCause {
+    /// The entry was expired.
+    Expired,
+    /// The entry was evicted because the cache was full.
+    Size,
+    /// The entry was removed by `remove`, `invalidate` or `clear`.
+    Explicit,
+    /// The value of the entry was replaced by inserting the same key again.
+    Replaced,
+}
+
+/// A callback invoked with every entry removed from a cache.
+pub(crate) type EvictionListener<K, V> = Arc<dyn Fn(&K, &V, RemovalCause) + Send + Sync>;
@@ -0,0 +1,365 @@
+use std::{
+    borrow::Borrow,
+    cell::RefCell,
+    convert::Infallible,
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    expiry,
+    fifo::{Fifo, Map},
+    listener::EvictionListener,
+    Clock, RemovalCause, Value,
+};
+
+/// The entries of a [`LocalCache`], behind its `RefCell`.
+struct Inner<K, V> {
+    map: Map<K, V>,
+    fifo: Fifo<K, V>,
+}
+
+/// A single-threaded cache, for caches owned by a thread or by a task. Build one
+/// with [`CacheBuilder::build_local`](crate::CacheBuilder::build_local).
+///
+/// It has the semantics of [`Cache`](crate::Cache), but keeps its entries in a
+/// plain `HashMap` behind a `RefCell`, without any atomics or locks. So it can be
+/// sent to another thread but not shared between threads, and lookups and writes
+/// are much cheaper when the cache is never shared anyway, e.g. inside the tasks of
+/// a thread-per-core runtime.
+///
+/// Entries are evicted in insertion order, and expired entries are removed from the
+/// head of the queue by lookups and writes. The eviction listener is called once the
+/// cache is no longer borrowed, so it may access the cache.
+///
+/// Only the core of the API of `Cache` is available: lookups, with
+/// [`get`](Self::get), [`peek`](Self::peek), [`with`](Self::with),
+/// [`contains_key`](Self::contains_key), [`ttl_remaining`](Self::ttl_remaining) and
+/// [`snapshot`](Self::snapshot), writes, with [`insert`](Self::insert),
+/// [`insert_with_ttl`](Self::insert_with_ttl), [`modify`](Self::modify),
+/// [`touch`](Self::touch) and [`get_or_insert_with`](Self::get_or_insert_with), and
+/// removals, with [`remove`](Self::remove), [`retain`](Self::retain),
+/// [`clear`](Self::clear) and [`evict_expired`](Self::evict_expired). The options of
+/// the builder which need more than that, e.g. weights or eviction policies, are
+/// rejected by [`CacheBuilder::try_build_local`](crate::CacheBuilder::try_build_local).
+pub struct LocalCache<K, V> {
+    inner: RefCell<Inner<K, V>>,
+    listener: Option<EvictionListener<K, V>>,
+    capacity: usize,
+    ttl: Duration,
+    // Only set when the system clock isn't used, to avoid the dynamic dispatch.
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl<K, V> LocalCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    /// Create a new cache with the given capacity and time-to-live (TTL) for values.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        crate::CacheBuilder::new()
+            .capacity(capacity)
+            .ttl(ttl)
+            .build_local()
+    }
+
+    pub(crate) fn from_parts(
+        capacity: usize,
+        ttl: Duration,
+        listener: Option<EvictionListener<K, V>>,
+        clock: Option<Arc<dyn Clock>>,
+        hasher: ahash::RandomState,
+    ) -> Self {
+        Self {
+            inner: RefCell::new(Inner {
+                map: Fifo::map(capacity, hasher),
+                fifo: Fifo::new(capacity, ttl),
+            }),
+            listener,
+            capacity,
+            ttl,
+            clock,
+        }
+    }
+
+    /// Get the number of entries in the cache.
+    pub fn len(&self) -> usize {
+        self.inner.borrow().map.len()
+    }
+
+    /// Check if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the capacity of the cache.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Call `f` with a reference to the value associated with the given key, and
+    /// return its result.
+    ///
+    /// The cache is borrowed while `f` runs, so `f` must not access it, or it will
+    /// panic.
+    pub fn with<R, Q>(&self, key: &Q, f: impl FnOnce(&V) -> R) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.borrow().map.get(key).map(|e| f(&e.value))
+    }
+
+    /// Check if the cache contains a value for the given key which isn't expired.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.now();
+        self.inner
+            .borrow()
+            .map
+            .get(key)
+            .is_some_and(|e| now <= e.expire_at)
+    }
+
+    /// Get how long the value associated with the given key has left to live, which
+    /// is zero if it's expired.
+    ///
+    /// Like [`contains_key`](Self::contains_key), this doesn't remove expired entries.
+    pub fn ttl_remaining<Q>(&self, key: &Q) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.now();
+        self.inner
+            .borrow()
+            .map
+            .get(key)
+            .map(|e| e.expire_at.saturating_duration_since(now))
+    }
+
+    /// Insert a key-value pair in the cache.
+    ///
+    /// If the cache is full, it will evict the oldest entry. If the key already
+    /// exists, its value is updated and its ttl restarts, but it keeps its position
+    /// in the queue.
+    pub fn insert(&self, key: K, value: V) {
+        self.write(|inner, now| {
+            let expire_at = expiry::expire_after(now, self.ttl);
+            inner
+                .fifo
+                .insert(&mut inner.map, key, value, now, expire_at);
+        });
+    }
+
+    /// Insert a key-value pair in the cache which expires after the given `ttl`
+    /// instead of the cache-wide one, see
+    /// [`Cache::insert_with_ttl`](crate::Cache::insert_with_ttl).
+    pub fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) {
+        self.write(|inner, now| {
+            let expire_at = expiry::expire_after(now, ttl);
+            inner
+                .fifo
+                .insert(&mut inner.map, key, value, now, expire_at);
+        });
+    }
+
+    /// Call `f` with a mutable reference to the value associated with the given key,
+    /// and return its result.
+    ///
+    /// The entry keeps its position in the queue and its ttl. The cache is borrowed
+    /// while `f` runs, so `f` must not access it, or it will panic.
+    pub fn modify<R, Q>(&self, key: &Q, f: impl FnOnce(&mut V) -> R) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.write(|inner, _| inner.map.get_mut(key).map(|e| f(&mut e.value)))
+    }
+
+    /// Restart the ttl of the entry associated with the given key, without changing
+    /// its value, and return whether it was found.
+    ///
+    /// As with [`Cache::touch`](crate::Cache::touch), an expired entry isn't brought
+    /// back to life, and `false` is returned instead.
+    pub fn touch<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.write(|inner, now| match inner.map.get_mut(key) {
+            Some(e) if now <= e.expire_at => {
+                e.expire_at = expiry::expire_after(now, self.ttl);
+                true
+            }
+            _ => false,
+        })
+    }
+
+    /// Remove the entry associated with the given key and return its value.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (key, e) = self.write(|inner, _| inner.fifo.remove(&mut inner.map, key))?;
+        if let Some(listener) = &self.listener {
+            listener(&key, &e.value, RemovalCause::Explicit);
+        }
+        Some(e.value)
+    }
+
+    /// Keep only the entries for which `f` returns true.
+    ///
+    /// The other entries are reported to the eviction listener as explicit removals.
+    /// The cache is borrowed while `f` runs, so `f` must not access it, or it will
+    /// panic.
+    pub fn retain(&self, f: impl Fn(&K, &V) -> bool) {
+        self.write(|inner, _| {
+            let removed: Vec<K> = inner
+                .map
+                .iter()
+                .filter(|(k, e)| !f(k, &e.value))
+                .map(|(k, _)| k.clone())
+                .collect();
+            for key in removed {
+                let (key, e) = inner.fifo.remove(&mut inner.map, &key).unwrap();
+                inner
+                    .fifo
+                    .removed
+                    .push((key, e.value, RemovalCause::Explicit));
+            }
+        });
+    }
+
+    /// Discard all entries in the cache.
+    pub fn clear(&self) {
+        self.write(|inner, _| inner.fifo.clear(&mut inner.map));
+    }
+
+    /// Remove all expired entries from the cache, including the ones stuck behind an
+    /// entry which is still alive.
+    pub fn evict_expired(&self) {
+        self.write(|inner, now| {
+            let expired: Vec<K> = inner
+                .map
+                .iter()
+                .filter(|(_, e)| now > e.expire_at)
+                .map(|(k, _)| k.clone())
+                .collect();
+            for key in expired {
+                let e = inner.map.remove(&key).unwrap();
+                inner
+                    .fifo
+                    .removed
+                    .push((key, e.value, RemovalCause::Expired));
+            }
+        });
+    }
+
+    /// Apply `f` to the entries, and report the removed entries once they're no
+    /// longer borrowed.
+    ///
+    /// Expired entries at the head of the queue are removed first.
+    fn write<R>(&self, f: impl FnOnce(&mut Inner<K, V>, Instant) -> R) -> R {
+        let now = self.now();
+        let (result, removed) = {
+            let mut inner = self.inner.borrow_mut();
+            let inner = &mut *inner;
+            inner.fifo.expire(&mut inner.map, now);
+            let result = f(inner, now);
+            (result, std::mem::take(&mut inner.fifo.removed))
+        };
+        if let Some(listener) = &self.listener {
+            for (k, v, cause) in &removed {
+                listener(k, v, *cause);
+            }
+        }
+        result
+    }
+
+    fn now(&self) -> Instant {
+        match &self.clock {
+            Some(clock) => clock.now(),
+            None => Instant::now(),
+        }
+    }
+}
+
+impl<K, V> LocalCache<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    /// Get the value associated with the given key, if it exists.
+    ///
+    /// As with [`Cache::get`](crate::Cache::get), an expired value which hasn't been
+    /// removed yet is returned, and reported with [`Value::is_expired`].
+    pub fn get<Q>(&self, key: &Q) -> Option<Value<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.write(|inner, now| inner.map.get(key).map(|e| e.to_value(now)))
+    }
+
+    /// Get the value associated with the given key like [`get`](Self::get), but
+    /// without removing the expired entries at the head of the queue.
+    pub fn peek<Q>(&self, key: &Q) -> Option<Value<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.now();
+        self.inner.borrow().map.get(key).map(|e| e.to_value(now))
+    }
+
+    /// Collect all entries in the cache, in no particular order.
+    ///
+    /// Like [`get`](Self::get), expired entries which haven't been removed yet are
+    /// included, and can be told apart with [`Value::is_expired`].
+    pub fn snapshot(&self) -> Vec<(K, Value<V>)> {
+        let now = self.now();
+        self.inner
+            .borrow()
+            .map
+            .iter()
+            .map(|(k, e)| (k.clone(), e.to_value(now)))
+            .collect()
+    }
+
+    /// Get the value associated with the given key, or insert the one computed by
+    /// `f` if it's missing or expired.
+    ///
+    /// `f` is called while the cache isn't borrowed, so it may access the cache.
+    pub fn get_or_insert_with(&self, key: K, f: impl FnOnce() -> V) -> Value<V> {
+        match self.try_get_or_insert_with(key, || Ok::<_, Infallible>(f())) {
+            Ok(value) => value,
+            Err(e) => match e {},
+        }
+    }
+
+    /// Like [`get_or_insert_with`](Self::get_or_insert_with), but `f` may fail, in
+    /// which case nothing is inserted and the error is returned.
+    pub fn try_get_or_insert_with<E>(
+        &self,
+        key: K,
+        f: impl FnOnce() -> Result<V, E>,
+    ) -> Result<Value<V>, E> {
+        if let Some(value) = self.get(&key).filter(|v| !v.is_expired()) {
+            return Ok(value);
+        }
+        let value = f()?;
+        Ok(self.write(|inner, now| {
+            let expire_at = expiry::expire_after(now, self.ttl);
+            let version = inner
+                .fifo
+                .insert(&mut inner.map, key, value.clone(), now, expire_at);
+            Value::inserted(value, now, expire_at, version)
+        }))
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+};
+
+use crate::{Cache, Value};
+
+/// The result of looking up a key in a cache of optional values, see
+/// [`Cache::lookup`].
+pub enum CacheResult<V> {
+    /// The key has a value, which may be expired.
+    Hit(Value<V>),
+    /// The key is cached as not existing, and this hasn't expired yet.
+    NegativeHit,
+    /// The key isn't in the cache, or its negative entry expired.
+    Miss,
+}
+
+/// Negative caching: `None` is cached for keys which don't exist upstream, with a
+/// ttl of its own.
+impl<K, V, S> Cache<K, Option<V>, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    /// Cache that the given key doesn't exist.
+    ///
+    /// This expires after the ttl set with
+    /// [`CacheBuilder::negative_ttl`](crate::CacheBuilder::negative_ttl), or the
+    /// cache-wide one if there is none.
+    pub fn insert_negative(&self, key: K) {
+        self.insert_with_ttl(key, None, self.negative_ttl.unwrap_or(self.ttl));
+    }
+}
+
+impl<K, V, S> Cache<K, Option<V>, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    /// Look up the given key like [`get`](Self::get), telling apart values from keys
+    /// cached as not existing with [`insert_negative`](Self::insert_negative).
+    ///
+    /// Unlike values, expired negative entries are reported as a miss.
+    pub fn lookup<Q>(&self, key: &Q) -> CacheResult<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let Some(value) = self.get(key) else {
+            return CacheResult::Miss;
+        };
+        let Value {
+            value,
+            expire_at,
+            is_expired,
+            inserted_at,
+            read_at,
+            version,
+        } = value;
+        match value {
+            Some(value) => CacheResult::Hit(Value {
+                value,
+                expire_at,
+                is_expired,
+                inserted_at,
+                read_at,
+                version,
+            }),
+            None if is_expired => CacheResult::Miss,
+            None => CacheResult::NegativeHit,
+        }
+    }
+}
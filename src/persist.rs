@@ -0,0 +1,127 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    time::{Duration, Instant},
+};
+
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::Cache;
+
+/// An entry as it's dumped, with its remaining ttl.
+#[derive(Serialize)]
+struct DumpedEntry<'a, K, V> {
+    key: &'a K,
+    value: &'a V,
+    ttl: Duration,
+}
+
+/// An entry as it's restored, see [`DumpedEntry`].
+#[derive(Deserialize)]
+struct RestoredEntry<K, V> {
+    key: K,
+    value: V,
+    ttl: Duration,
+}
+
+impl<K, V, S> Cache<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    /// Serialize the entries in the cache which aren't expired, so they can be loaded
+    /// back with [`restore`](Self::restore), e.g. after a restart.
+    ///
+    /// Entries are written in insertion order, oldest first, so restoring them keeps
+    /// the order they're evicted in. Instants can't be serialized, so each entry is
+    /// written with its ttl remaining at the time of the dump.
+    pub fn dump<Ser>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error>
+    where
+        Ser: Serializer,
+        K: Serialize,
+        V: Serialize,
+    {
+        serialize_entries(serializer, &self.dump_entries())
+    }
+
+    /// Collect the entries which aren't expired with their remaining ttl, oldest
+    /// first, so no lock is held while serializing them.
+    pub(crate) fn dump_entries(&self) -> Vec<(K, V, Duration)> {
+        let now = self.now();
+        let mut entries: Vec<_> = self
+            .map
+            .iter()
+            .filter(|e| !self.is_cleared(e.id))
+            .filter_map(|e| {
+                let ttl = self.deadline(&e).checked_duration_since(now)?;
+                Some((e.id, e.key().clone(), e.value.clone(), ttl))
+            })
+            .collect();
+        // Ids are handed out in insertion order.
+        entries.sort_unstable_by_key(|(id, ..)| *id);
+        entries
+            .into_iter()
+            .map(|(_, key, value, ttl)| (key, value, ttl))
+            .collect()
+    }
+
+    /// Insert the entries serialized with [`dump`](Self::dump).
+    ///
+    /// Each entry expires after the ttl it had remaining when it was dumped, so the
+    /// time between the dump and the restore isn't counted. Entries are inserted as
+    /// with [`insert`](Self::insert), so they replace existing ones and go through
+    /// the eviction and admission policies of this cache.
+    ///
+    /// Returns an error without inserting anything if an entry has a ttl too large to
+    /// compute its expiration, which can only come from a corrupted dump.
+    pub fn restore<'de, D>(&self, deserializer: D) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+        K: Deserialize<'de>,
+        V: Deserialize<'de>,
+    {
+        let now = self.now();
+        for (key, value, expire_at) in deserialize_entries(deserializer, now)? {
+            self.insert_entry(key, value, now, expire_at, None, None);
+        }
+        self.do_expire(now);
+        Ok(())
+    }
+}
+
+/// Serialize entries collected with [`Cache::dump_entries`].
+pub(crate) fn serialize_entries<Ser, K, V>(
+    serializer: Ser,
+    entries: &[(K, V, Duration)],
+) -> Result<Ser::Ok, Ser::Error>
+where
+    Ser: Serializer,
+    K: Serialize,
+    V: Serialize,
+{
+    serializer.collect_seq(entries.iter().map(|(key, value, ttl)| DumpedEntry {
+        key,
+        value,
+        ttl: *ttl,
+    }))
+}
+
+/// Deserialize dumped entries with the instant they expire at, or return an error
+/// if one of them can't be computed.
+pub(crate) fn deserialize_entries<'de, D, K, V>(
+    deserializer: D,
+    now: Instant,
+) -> Result<Vec<(K, V, Instant)>, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Deserialize<'de>,
+    V: Deserialize<'de>,
+{
+    Vec::<RestoredEntry<K, V>>::deserialize(deserializer)?
+        .into_iter()
+        .map(|e| match now.checked_add(e.ttl) {
+            Some(expire_at) => Ok((e.key, e.value, expire_at)),
+            None => Err(D::Error::custom("ttl too large")),
+        })
+        .collect()
+}
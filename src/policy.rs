@@ -0,0 +1,45 @@
+/// The policy used to choose which entry to evict when a [`Cache`](crate::Cache)
+/// is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum EvictionPolicy {
+    /// Evict the oldest inserted entry.
+    #[default]
+    Fifo,
+    /// Evict the least recently used entry.
+    ///
+    /// This is approximated with the CLOCK algorithm: reading an entry only marks it
+    /// as visited, and a visited entry is moved back to the tail of the queue instead
+    /// of being evicted, which keeps reads as cheap as in fifo mode.
+    Lru,
+}
+
+/// The policy used to decide if a new entry is admitted into a full
+/// [`Cache`](crate::Cache).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum AdmissionPolicy {
+    /// Always admit new entries, evicting an existing one to make room.
+    #[default]
+    Always,
+    /// Only admit a new entry if its key is accessed more often than the key of the
+    /// entry it would evict, like TinyLFU.
+    ///
+    /// Access frequencies are estimated with a count-min sketch that is updated on
+    /// every lookup and insertion, which protects hot entries from being flushed out
+    /// by scans of keys that are only used once.
+    TinyLfu,
+}
+
+/// The priority of an entry inserted with
+/// [`Cache::insert_with_priority`](crate::Cache::insert_with_priority).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// The entry is evicted as usual when the cache is full.
+    #[default]
+    Normal,
+    /// The entry is moved back to the tail of the queue instead of being evicted, so
+    /// it's only removed once it expires or when it's invalidated.
+    ///
+    /// Pinned entries still take room in the cache. When the cache is full of
+    /// pinned entries, the oldest one is evicted anyway.
+    Pinned,
+}
@@ -0,0 +1,174 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, PoisonError, RwLock, Weak,
+    },
+    time::Instant,
+};
+
+use crossbeam_utils::CachePadded;
+
+use crate::{Cache, CacheBuilder, CacheConfigError};
+
+/// A cache sharing a [`CapacityPool`], as seen by the pool.
+pub(crate) trait PoolMember: Send + Sync {
+    /// Get when the entry at the head of the queue was written, unless it's empty.
+    fn oldest_written_at(&self) -> Option<Instant>;
+
+    /// Evict the entry at the head of the queue, and return whether one was.
+    fn evict_oldest(&self) -> bool;
+}
+
+impl<K, V, S> PoolMember for Cache<K, V, S>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    S: BuildHasher + Clone + Send + Sync,
+{
+    fn oldest_written_at(&self) -> Option<Instant> {
+        Cache::oldest_written_at(self)
+    }
+
+    fn evict_oldest(&self) -> bool {
+        Cache::evict_oldest(self)
+    }
+}
+
+/// The state shared by the caches of a [`CapacityPool`].
+pub(crate) struct Pool {
+    max: u64,
+    used: CachePadded<AtomicU64>,
+    members: RwLock<Vec<Weak<dyn PoolMember>>>,
+}
+
+impl Pool {
+    pub(crate) fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub(crate) fn used(&self) -> u64 {
+        self.used.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn add(&self, weight: u64) {
+        self.used.fetch_add(weight, Ordering::AcqRel);
+    }
+
+    pub(crate) fn sub(&self, weight: u64) {
+        self.used.fetch_sub(weight, Ordering::AcqRel);
+    }
+
+    /// Evict the oldest entries of all the caches until the total weight fits.
+    ///
+    /// Each time, the cache whose head was written the longest ago is picked, so the
+    /// caches are drained together in the order their entries were written. A cache
+    /// which fails to evict anything, e.g. because all its entries are pinned, is
+    /// skipped from then on.
+    pub(crate) fn shrink(&self) {
+        if self.used() <= self.max {
+            return;
+        }
+        // Don't hold the lock while evicting, as listeners may build new caches.
+        let mut members: Vec<_> = self
+            .members
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+        while self.used() > self.max {
+            let oldest = members
+                .iter()
+                .enumerate()
+                .filter_map(|(i, m)| Some((m.oldest_written_at()?, i)))
+                .min();
+            let Some((_, i)) = oldest else {
+                break;
+            };
+            if !members[i].evict_oldest() {
+                members.swap_remove(i);
+            }
+        }
+    }
+}
+
+/// A maximum total weight shared by several caches.
+///
+/// Caches built with [`build`](Self::build) count the weight of their entries
+/// toward the pool on top of their own capacity and maximum weight. Once the total
+/// exceeds the maximum, the cache being written to evicts entries from whichever
+/// cache of the pool holds the oldest ones, until it fits again. So an idle cache
+/// gives its room to a busy one, instead of every cache being sized on its own.
+///
+/// Entries evicted from another cache are reported to its own eviction listener,
+/// on the thread of the write which needed the room. The pool is cheap to clone,
+/// and all the clones share the same caches.
+#[derive(Clone)]
+pub struct CapacityPool {
+    inner: Arc<Pool>,
+}
+
+impl CapacityPool {
+    /// Create a pool with the given maximum total weight.
+    ///
+    /// Every entry weighs 1 unless its cache has a
+    /// [weigher](CacheBuilder::weigher), so the caches of a pool should weigh their
+    /// entries in the same unit, e.g. bytes.
+    pub fn new(max_weight: u64) -> Self {
+        Self {
+            inner: Arc::new(Pool {
+                max: max_weight,
+                used: CachePadded::new(AtomicU64::new(0)),
+                members: RwLock::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Get the maximum total weight of the entries of the caches.
+    pub fn max_weight(&self) -> u64 {
+        self.inner.max
+    }
+
+    /// Get the total weight of the entries of the caches.
+    pub fn weight(&self) -> u64 {
+        self.inner.used()
+    }
+
+    /// Build a cache sharing the pool.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid, see [`try_build`](Self::try_build).
+    pub fn build<K, V, S>(&self, builder: CacheBuilder<K, V, S>) -> Arc<Cache<K, V, S>>
+    where
+        K: Eq + Hash + Clone + Send + Sync + 'static,
+        V: Send + Sync + 'static,
+        S: BuildHasher + Clone + Send + Sync + 'static,
+    {
+        self.try_build(builder).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Build a cache sharing the pool, or return an error if the configuration is
+    /// invalid, see [`CacheBuilder::try_build`].
+    pub fn try_build<K, V, S>(
+        &self,
+        builder: CacheBuilder<K, V, S>,
+    ) -> Result<Arc<Cache<K, V, S>>, CacheConfigError>
+    where
+        K: Eq + Hash + Clone + Send + Sync + 'static,
+        V: Send + Sync + 'static,
+        S: BuildHasher + Clone + Send + Sync + 'static,
+    {
+        let cache = Arc::new(builder.pool(self.inner.clone()).try_build()?);
+        let member: Arc<dyn PoolMember> = cache.clone();
+        let mut members = self
+            .inner
+            .members
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        members.retain(|m| m.strong_count() > 0);
+        members.push(Arc::downgrade(&member));
+        Ok(cache)
+    }
+}
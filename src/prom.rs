@@ -0,0 +1,165 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    sync::{Arc, Weak},
+};
+
+use prometheus::{
+    core::{Collector, Desc},
+    proto::MetricFamily,
+    IntCounter, IntGauge, Opts, Registry,
+};
+
+use crate::{Cache, CacheStats};
+
+/// The counters exported by [`Cache::register_metrics`], in the order of
+/// the fields of [`CacheStats`](crate::CacheStats), with their help.
+const COUNTERS: [(&str, &str); 6] = [
+    (
+        "fastcache_hits_total",
+        "Lookups which returned a value that was not expired.",
+    ),
+    ("fastcache_misses_total", "Lookups which returned nothing."),
+    (
+        "fastcache_stale_hits_total",
+        "Lookups which returned an expired value.",
+    ),
+    (
+        "fastcache_insertions_total",
+        "Values inserted in the cache.",
+    ),
+    (
+        "fastcache_expirations_total",
+        "Entries removed because they were expired.",
+    ),
+    (
+        "fastcache_evictions_total",
+        "Entries removed because the cache was full.",
+    ),
+];
+
+/// What a [`CacheCollector`] reads from a cache.
+pub(crate) trait Metered {
+    fn len(&self) -> usize;
+    fn capacity(&self) -> usize;
+    fn stats(&self) -> CacheStats;
+}
+
+impl<K, V, S> Metered for Cache<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    fn len(&self) -> usize {
+        Cache::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        Cache::capacity(self)
+    }
+
+    fn stats(&self) -> CacheStats {
+        Cache::stats(self)
+    }
+}
+
+/// Collects the metrics of a cache when the registry is scraped.
+struct CacheCollector<C> {
+    cache: Weak<C>,
+    len: IntGauge,
+    capacity: IntGauge,
+    // Counters can't be set, so fresh ones are made from these on each scrape, and
+    // the ones kept here only describe them.
+    counter_opts: Vec<Opts>,
+    counters: Vec<IntCounter>,
+}
+
+impl<C> Collector for CacheCollector<C>
+where
+    C: Metered + Send + Sync,
+{
+    fn desc(&self) -> Vec<&Desc> {
+        let gauges = [&self.len, &self.capacity]
+            .into_iter()
+            .flat_map(|g| g.desc());
+        gauges
+            .chain(self.counters.iter().flat_map(|c| c.desc()))
+            .collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        // Nothing is reported once the cache is dropped.
+        let Some(cache) = self.cache.upgrade() else {
+            return Vec::new();
+        };
+        self.len.set(cache.len() as i64);
+        self.capacity.set(cache.capacity() as i64);
+        let stats = cache.stats();
+        let values = [
+            stats.hits,
+            stats.misses,
+            stats.stale_hits,
+            stats.insertions,
+            stats.expirations,
+            stats.evictions,
+        ];
+        let mut families = self.len.collect();
+        families.extend(self.capacity.collect());
+        for (opts, value) in self.counter_opts.iter().zip(values) {
+            // The options were checked when the collector was registered.
+            let counter = IntCounter::with_opts(opts.clone()).expect("valid counter options");
+            counter.inc_by(value);
+            families.extend(counter.collect());
+        }
+        families
+    }
+}
+
+impl<K, V, S> Cache<K, V, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    S: BuildHasher + Clone + Send + Sync + 'static,
+{
+    /// Register the metrics of the cache in a Prometheus registry, labeled with
+    /// `cache="<name>"` so that several caches can share a registry.
+    ///
+    /// The `fastcache_len` and `fastcache_capacity` gauges and the
+    /// `fastcache_hits_total`, `fastcache_misses_total`, `fastcache_stale_hits_total`,
+    /// `fastcache_insertions_total`, `fastcache_expirations_total` and
+    /// `fastcache_evictions_total` counters are read from the cache when the registry
+    /// is scraped, so this adds nothing to lookups and inserts besides the
+    /// [`stats`](Self::stats) counters. The registry only holds a weak reference to
+    /// the cache, and stops reporting it once it's dropped.
+    ///
+    /// Returns an error if metrics with the same name were already registered.
+    pub fn register_metrics(
+        self: &Arc<Self>,
+        registry: &Registry,
+        name: &str,
+    ) -> prometheus::Result<()> {
+        register(self, registry, name)
+    }
+}
+
+/// Register a collector for the given cache, see [`Cache::register_metrics`].
+pub(crate) fn register<C>(cache: &Arc<C>, registry: &Registry, name: &str) -> prometheus::Result<()>
+where
+    C: Metered + Send + Sync + 'static,
+{
+    let opts = |metric: &str, help: &str| Opts::new(metric, help).const_label("cache", name);
+    let counter_opts: Vec<_> = COUNTERS.iter().map(|(m, h)| opts(m, h)).collect();
+    let collector = CacheCollector {
+        cache: Arc::downgrade(cache),
+        len: IntGauge::with_opts(opts("fastcache_len", "Number of entries in the cache."))?,
+        capacity: IntGauge::with_opts(opts(
+            "fastcache_capacity",
+            "Maximum number of entries in the cache.",
+        ))?,
+        counters: counter_opts
+            .iter()
+            .map(|opts| IntCounter::with_opts(opts.clone()))
+            .collect::<prometheus::Result<_>>()?,
+        counter_opts,
+    };
+    registry.register(Box::new(collector))
+}
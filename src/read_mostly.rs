@@ -0,0 +1,218 @@
+use std::{
+    borrow::Borrow,
+    hash::Hash,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use arc_swap::ArcSwap;
+
+use crate::{
+    expiry,
+    fifo::{Fifo, Map},
+    listener::EvictionListener,
+    Clock, RemovalCause, Value,
+};
+
+/// A cache for read-mostly workloads, which serves lookups from an immutable
+/// snapshot of its entries. Build one with
+/// [`CacheBuilder::build_read_mostly`](crate::CacheBuilder::build_read_mostly).
+///
+/// Lookups are wait-free: they never take a lock, and don't write to memory shared
+/// with other threads besides the reference count of the snapshot. In exchange,
+/// writes are serialized by a lock and publish a new snapshot by copying the whole
+/// map, so they take time proportional to the number of entries. This pays off for
+/// a small set of hot keys which is rarely written, and [`insert_many`] amortizes
+/// the copy over a batch of writes.
+///
+/// Entries are evicted in insertion order, and expired entries are removed by
+/// writes. Lookups report the expired entries which haven't been removed yet with
+/// [`Value::is_expired`], like [`Cache::get`](crate::Cache::get).
+///
+/// Besides [`insert_many`], only lookups, inserts, removals and
+/// [`clear`](Self::clear) are available, and the options of the builder which need
+/// more are rejected by
+/// [`CacheBuilder::try_build_read_mostly`](crate::CacheBuilder::try_build_read_mostly).
+///
+/// [`insert_many`]: Self::insert_many
+pub struct ReadMostlyCache<K, V> {
+    snapshot: ArcSwap<Map<K, V>>,
+    // Only touched by writers, behind the lock.
+    writer: Mutex<Fifo<K, V>>,
+    listener: Option<EvictionListener<K, V>>,
+    capacity: usize,
+    ttl: Duration,
+    // Only set when the system clock isn't used, to avoid the dynamic dispatch.
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl<K, V> ReadMostlyCache<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    /// Create a new cache with the given capacity and time-to-live (TTL) for values.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        crate::CacheBuilder::new()
+            .capacity(capacity)
+            .ttl(ttl)
+            .build_read_mostly()
+    }
+
+    pub(crate) fn from_parts(
+        capacity: usize,
+        ttl: Duration,
+        listener: Option<EvictionListener<K, V>>,
+        clock: Option<Arc<dyn Clock>>,
+        hasher: ahash::RandomState,
+    ) -> Self {
+        Self {
+            snapshot: ArcSwap::from_pointee(Fifo::map(capacity, hasher)),
+            writer: Mutex::new(Fifo::new(capacity, ttl)),
+            listener,
+            capacity,
+            ttl,
+            clock,
+        }
+    }
+
+    /// Get the number of elements in the cache.
+    pub fn len(&self) -> usize {
+        self.snapshot.load().len()
+    }
+
+    /// Check if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the capacity of the cache.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Get the value associated with the given key, if it exists.
+    pub fn get<Q>(&self, key: &Q) -> Option<Value<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let snapshot = self.snapshot.load();
+        let e = snapshot.get(key)?;
+        Some(e.to_value(self.now()))
+    }
+
+    /// Call `f` with a reference to the value associated with the given key, and
+    /// return its result.
+    ///
+    /// Unlike [`get`](Self::get) this doesn't clone the value, and the snapshot it
+    /// reads stays valid as long as `f` runs, so `f` may write to the cache.
+    pub fn with<R, Q>(&self, key: &Q, f: impl FnOnce(&V) -> R) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.snapshot.load().get(key).map(|e| f(&e.value))
+    }
+
+    /// Check if the cache contains a value for the given key which isn't expired.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.now();
+        self.snapshot
+            .load()
+            .get(key)
+            .is_some_and(|e| now <= e.expire_at)
+    }
+
+    /// Insert a key-value pair in the cache.
+    ///
+    /// If the cache is full, it will evict the oldest entry. If the key already
+    /// exists, its value is updated and its ttl restarts, but it keeps its position
+    /// in the queue.
+    pub fn insert(&self, key: K, value: V) {
+        self.insert_many([(key, value)]);
+    }
+
+    /// Insert a key-value pair in the cache which expires after the given `ttl`
+    /// instead of the cache-wide one.
+    ///
+    /// Like with [`Cache::insert_with_ttl`](crate::Cache::insert_with_ttl), an entry
+    /// which expires before the ones queued in front of it is only removed once it
+    /// reaches the head of the queue, and lookups report it as expired until then.
+    pub fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) {
+        self.write(|writer, map, now| {
+            writer.insert(map, key, value, now, expiry::expire_after(now, ttl));
+        });
+    }
+
+    /// Insert many key-value pairs in the cache, as with [`insert`](Self::insert).
+    ///
+    /// The whole batch is published in a single snapshot, so this is much cheaper
+    /// than inserting the entries one by one.
+    pub fn insert_many(&self, entries: impl IntoIterator<Item = (K, V)>) {
+        self.write(|writer, map, now| {
+            for (key, value) in entries {
+                writer.insert(map, key, value, now, expiry::expire_after(now, self.ttl));
+            }
+        });
+    }
+
+    /// Remove the entry associated with the given key and return its value.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if !self.snapshot.load().contains_key(key) {
+            return None;
+        }
+        self.write(|writer, map, _| {
+            let (key, e) = writer.remove(map, key)?;
+            if self.listener.is_some() {
+                writer
+                    .removed
+                    .push((key, e.value.clone(), RemovalCause::Explicit));
+            }
+            Some(e.value)
+        })
+    }
+
+    /// Discard all entries in the cache.
+    pub fn clear(&self) {
+        self.write(|writer, map, _| writer.clear(map));
+    }
+
+    /// Apply `f` to a copy of the current snapshot under the writer lock, then
+    /// publish it, and report the removed entries once the lock is released.
+    ///
+    /// Expired entries at the head of the queue are removed first.
+    fn write<R>(&self, f: impl FnOnce(&mut Fifo<K, V>, &mut Map<K, V>, Instant) -> R) -> R {
+        let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        // Only writers replace the snapshot, and they hold the lock, so this is
+        // the latest one.
+        let mut map = Map::clone(&self.snapshot.load());
+        let now = self.now();
+        writer.expire(&mut map, now);
+        let result = f(&mut writer, &mut map, now);
+        self.snapshot.store(Arc::new(map));
+        let removed = std::mem::take(&mut writer.removed);
+        drop(writer);
+        if let Some(listener) = &self.listener {
+            for (k, v, cause) in &removed {
+                listener(k, v, *cause);
+            }
+        }
+        result
+    }
+
+    fn now(&self) -> Instant {
+        match &self.clock {
+            Some(clock) => clock.now(),
+            None => Instant::now(),
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, PoisonError,
+    },
+    thread,
+    time::Duration,
+};
+
+use dashmap::mapref::entry::Entry as MapEntry;
+
+use crate::{builder::Spawner, expiry, Cache, Value};
+
+/// A task a cache runs in the background, given to its
+/// [spawner](crate::CacheBuilder::spawner).
+pub type BackgroundTask = Box<dyn FnOnce() + Send>;
+
+/// The most threads the default spawner of a cache runs at once.
+const MAX_THREADS: usize = 16;
+
+/// Create the default spawner, which runs each task on a new thread, and gives it
+/// back if [`MAX_THREADS`] are already running or if the thread can't be spawned.
+pub(crate) fn thread_spawner() -> Spawner {
+    let running = Arc::new(AtomicUsize::new(0));
+    Arc::new(move |task: BackgroundTask| {
+        if running.fetch_add(1, Ordering::Relaxed) >= MAX_THREADS {
+            running.fetch_sub(1, Ordering::Relaxed);
+            return Err(task);
+        }
+        // Shared with the thread so that it can be taken back if spawning fails.
+        let task = Arc::new(Mutex::new(Some(task)));
+        let (queued, done) = (Arc::clone(&task), Arc::clone(&running));
+        let spawned = thread::Builder::new()
+            .name("fastcache-refresh".to_owned())
+            .spawn(move || {
+                let task = queued.lock().unwrap_or_else(PoisonError::into_inner).take();
+                if let Some(task) = task {
+                    let _ = panic::catch_unwind(AssertUnwindSafe(task));
+                }
+                done.fetch_sub(1, Ordering::Relaxed);
+            });
+        match spawned {
+            Ok(_) => Ok(()),
+            Err(_) => {
+                running.fetch_sub(1, Ordering::Relaxed);
+                let task = task.lock().unwrap_or_else(PoisonError::into_inner).take();
+                Err(task.expect("the task of a thread which wasn't spawned is taken"))
+            }
+        }
+    })
+}
+
+/// A load started by [`Cache::get_fresh_or_stale`], which the readers of its key
+/// wait for.
+pub(crate) struct FreshLoad<V> {
+    // Set once the load is done, to `None` if it panicked.
+    value: Mutex<Option<Option<Value<V>>>>,
+    done: Condvar,
+}
+
+impl<V: Clone> FreshLoad<V> {
+    fn finish(&self, value: Option<Value<V>>) {
+        *self.value.lock().unwrap_or_else(PoisonError::into_inner) = Some(value);
+        self.done.notify_all();
+    }
+
+    /// Wait for the value for up to `budget`.
+    fn wait(&self, budget: Duration) -> Option<Value<V>> {
+        let value = self.value.lock().unwrap_or_else(PoisonError::into_inner);
+        let (value, _) = self
+            .done
+            .wait_timeout_while(value, budget, |value| value.is_none())
+            .unwrap_or_else(PoisonError::into_inner);
+        value.clone().flatten()
+    }
+}
+
+impl<K, V, S> Cache<K, V, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+    S: BuildHasher + Clone + Send + Sync + 'static,
+{
+    /// Get the value associated with the given key, refreshing it in the background
+    /// with `refresh` if it is expired (stale-while-revalidate).
+    ///
+    /// An expired value is returned right away, and at most one refresh per key runs
+    /// at a time, on the [spawner](crate::CacheBuilder::spawner) of the cache;
+    /// `refresh` is dropped without running if one is already in flight, or if the
+    /// spawner rejects it. If the key doesn't exist, `refresh` is run on the
+    /// calling thread as with [`get_or_insert_with`](Self::get_or_insert_with), which
+    /// also waits for a background refresh of the same key instead of running twice.
+    pub fn get_with_refresh(
+        self: &Arc<Self>,
+        key: K,
+        refresh: impl FnOnce() -> V + Send + 'static,
+    ) -> Value<V> {
+        let value = match self.get(&key) {
+            Some(value) => value,
+            None => return self.get_or_insert_with(key, refresh),
+        };
+        if value.is_expired() {
+            self.spawn_refresh(key, move |cache, key| {
+                cache.get_or_insert_with(key.clone(), refresh);
+            });
+        }
+        value
+    }
+
+    /// Get a fresh value for the given key, falling back to the expired one if
+    /// loading it takes longer than `budget`.
+    ///
+    /// A value which isn't expired is returned right away. Otherwise `loader` is run
+    /// on the [spawner](crate::CacheBuilder::spawner) of the cache, and the value it
+    /// returns is inserted and returned if it completes within `budget`. If it
+    /// doesn't, or if it panics, the expired value is returned if there is one, and
+    /// nothing otherwise. A load which runs late isn't cancelled: it keeps running in
+    /// the background, and its value is still inserted once it completes. If the
+    /// spawner rejects the load, it runs on the calling thread regardless of
+    /// `budget`.
+    ///
+    /// At most one load per key runs at a time: callers finding one in flight wait
+    /// for it within their own `budget`, and their `loader` is dropped without
+    /// running.
+    pub fn get_fresh_or_stale(
+        self: &Arc<Self>,
+        key: K,
+        budget: Duration,
+        loader: impl FnOnce() -> V + Send + 'static,
+    ) -> Option<Value<V>> {
+        let stale = match self.get(&key) {
+            Some(value) if !value.is_expired() => return Some(value),
+            stale => stale,
+        };
+        let load = match self.fresh_loads.entry(key.clone()) {
+            MapEntry::Occupied(o) => Arc::clone(o.get()),
+            MapEntry::Vacant(v) => {
+                let load = Arc::new(FreshLoad {
+                    value: Mutex::new(None),
+                    done: Condvar::new(),
+                });
+                v.insert(Arc::clone(&load));
+                let (cache, running) = (Arc::clone(self), Arc::clone(&load));
+                let task = Box::new(move || {
+                    let value = panic::catch_unwind(AssertUnwindSafe(|| {
+                        cache.get_or_insert_with(key.clone(), loader)
+                    }));
+                    // The value is inserted by now, so later callers find it.
+                    cache.fresh_loads.remove(&key);
+                    running.finish(value.ok());
+                });
+                if let Err(task) = (self.spawner)(task) {
+                    task();
+                }
+                load
+            }
+        };
+        load.wait(budget).or(stale)
+    }
+
+    /// Get the value associated with the given key, reloading it with the
+    /// [refresher](crate::CacheBuilder::refresher) of the cache (refresh-ahead).
+    ///
+    /// A value older than [`refresh_after`](crate::CacheBuilder::refresh_after) is
+    /// returned right away and reloaded on the
+    /// [spawner](crate::CacheBuilder::spawner) of the cache, so that hot keys are
+    /// refreshed before they expire. The reloaded value is dropped if the key was
+    /// written meanwhile. A missing or expired value is loaded on the calling thread
+    /// as with [`get_or_insert_with`](Self::get_or_insert_with). Without a refresher,
+    /// this is the same as [`get`](Self::get).
+    pub fn get_or_refresh(self: &Arc<Self>, key: K) -> Option<Value<V>> {
+        let Some(refresher) = self.refresher.clone() else {
+            return self.get(&key);
+        };
+        let value = match self.get(&key) {
+            Some(value) if !value.is_expired() => value,
+            _ => {
+                let load_key = key.clone();
+                return Some(self.get_or_insert_with(key, move || refresher(&load_key)));
+            }
+        };
+        let due = self
+            .refresh_after
+            .is_some_and(|after| value.age() >= refresh_threshold(after, value.version()));
+        if due {
+            let version = value.version();
+            self.spawn_refresh(key, move |cache, key| {
+                let _ = cache.compare_and_insert(key.clone(), refresher(key), version);
+            });
+        }
+        Some(value)
+    }
+
+    /// Run `refresh` on the spawner, unless a refresh of the key is already in
+    /// flight. It's dropped if the spawner rejects it, so the stale value is kept.
+    fn spawn_refresh(self: &Arc<Self>, key: K, refresh: impl FnOnce(&Self, &K) + Send + 'static) {
+        if !self.refreshing.insert(key.clone()) {
+            return;
+        }
+        let (cache, refreshed) = (Arc::clone(self), key.clone());
+        let task = Box::new(move || {
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| refresh(&cache, &refreshed)));
+            cache.refreshing.remove(&refreshed);
+        });
+        if (self.spawner)(task).is_err() {
+            self.refreshing.remove(&key);
+        }
+    }
+}
+
+/// Get the age at which the value with the given version is refreshed, which is up
+/// to 10% earlier than `after`.
+fn refresh_threshold(after: Duration, version: u64) -> Duration {
+    after
+        .min(expiry::CENTURY)
+        .mul_f64(1.0 - 0.1 * jitter(version))
+}
+
+/// Map a seed to a pseudo-random number in `[0, 1)`.
+pub(crate) fn jitter(seed: u64) -> f64 {
+    (mix(seed) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Scramble the bits of a seed with a SplitMix64 finalizer, so that every bit of the
+/// result depends on all the bits of the seed.
+pub(crate) fn mix(seed: u64) -> u64 {
+    let mut x = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
@@ -0,0 +1,248 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use crate::{Cache, CacheBuilder, CapacityPool};
+
+/// The key a cache is registered with.
+#[derive(PartialEq, Eq, Hash)]
+enum Key {
+    Name(String),
+    Type(TypeId),
+}
+
+/// The operations of a registered cache which don't depend on its types.
+trait AnyCache: Send + Sync {
+    fn len(&self) -> usize;
+    fn weight(&self) -> u64;
+    fn clear(&self);
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> crate::CacheStats;
+}
+
+impl<K, V> AnyCache for Cache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+{
+    fn len(&self) -> usize {
+        Cache::len(self)
+    }
+
+    fn weight(&self) -> u64 {
+        Cache::weight(self)
+    }
+
+    fn clear(&self) {
+        Cache::clear(self);
+    }
+
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> crate::CacheStats {
+        Cache::stats(self)
+    }
+}
+
+struct Registered {
+    name: String,
+    cache: Arc<dyn AnyCache>,
+    // The same cache, to downcast it back to its types.
+    any: Arc<dyn Any + Send + Sync>,
+}
+
+/// A set of caches of different key and value types, looked up by name or by type,
+/// which may share a total weight.
+///
+/// With [`with_max_weight`](Self::with_max_weight), the registered caches share a
+/// [`CapacityPool`]: the total weight of their entries is bounded, and once it's
+/// exceeded the oldest entries of all the caches are evicted until it fits again,
+/// on top of the capacity and maximum weight of each cache. This gives one memory
+/// budget to caches of different entity types, instead of sizing each of them
+/// separately, as long as their [weighers](CacheBuilder::weigher) use the same unit.
+pub struct CacheRegistry {
+    caches: RwLock<HashMap<Key, Registered, ahash::RandomState>>,
+    pool: Option<CapacityPool>,
+}
+
+impl CacheRegistry {
+    /// Create an empty registry, whose caches don't share a total weight.
+    pub fn new() -> Self {
+        Self {
+            caches: RwLock::new(HashMap::default()),
+            pool: None,
+        }
+    }
+
+    /// Create an empty registry, whose caches share the given maximum total weight.
+    pub fn with_max_weight(max_weight: u64) -> Self {
+        Self {
+            pool: Some(CapacityPool::new(max_weight)),
+            ..Self::new()
+        }
+    }
+
+    /// Build a cache and register it under the given name, replacing the cache
+    /// registered under it, if any.
+    ///
+    /// The cache is given the name, unless the builder already has one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid, like [`CacheBuilder::build`].
+    pub fn register<K, V>(
+        &self,
+        name: impl Into<String>,
+        builder: CacheBuilder<K, V>,
+    ) -> Arc<Cache<K, V>>
+    where
+        K: Eq + Hash + Clone + Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        let name = name.into();
+        let builder = match builder.name.is_some() {
+            true => builder,
+            false => builder.name(name.clone()),
+        };
+        self.insert(Key::Name(name.clone()), name, builder)
+    }
+
+    /// Build a cache and register it under its key and value types, replacing the
+    /// cache registered under them, if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid, like [`CacheBuilder::build`].
+    pub fn register_typed<K, V>(&self, builder: CacheBuilder<K, V>) -> Arc<Cache<K, V>>
+    where
+        K: Eq + Hash + Clone + Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        let name = builder
+            .name
+            .clone()
+            .unwrap_or_else(|| std::any::type_name::<Cache<K, V>>().to_owned());
+        self.insert(Key::Type(TypeId::of::<Cache<K, V>>()), name, builder)
+    }
+
+    fn insert<K, V>(&self, key: Key, name: String, builder: CacheBuilder<K, V>) -> Arc<Cache<K, V>>
+    where
+        K: Eq + Hash + Clone + Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        let cache = match &self.pool {
+            Some(pool) => pool.build(builder),
+            None => Arc::new(builder.build()),
+        };
+        let registered = Registered {
+            name,
+            cache: cache.clone(),
+            any: cache.clone(),
+        };
+        self.caches
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(key, registered);
+        cache
+    }
+
+    /// Get the cache registered under the given name, if there is one with these
+    /// key and value types.
+    pub fn get<K, V>(&self, name: &str) -> Option<Arc<Cache<K, V>>>
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        self.lookup(&Key::Name(name.to_owned()))
+    }
+
+    /// Get the cache registered under the given key and value types with
+    /// [`register_typed`](Self::register_typed), if any.
+    pub fn get_typed<K, V>(&self) -> Option<Arc<Cache<K, V>>>
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        self.lookup(&Key::Type(TypeId::of::<Cache<K, V>>()))
+    }
+
+    fn lookup<K, V>(&self, key: &Key) -> Option<Arc<Cache<K, V>>>
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        let caches = self.caches.read().unwrap_or_else(PoisonError::into_inner);
+        caches.get(key)?.any.clone().downcast().ok()
+    }
+
+    /// Unregister the cache registered under the given name, and return whether
+    /// there was one.
+    ///
+    /// The cache keeps working for whoever still holds it, and stays in the pool of
+    /// the registry until it's dropped.
+    pub fn unregister(&self, name: &str) -> bool {
+        self.caches
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&Key::Name(name.to_owned()))
+            .is_some()
+    }
+
+    /// Get the names of the registered caches, in no particular order.
+    pub fn names(&self) -> Vec<String> {
+        self.with_caches(|c| c.name.clone())
+    }
+
+    /// Get the total number of entries in the registered caches.
+    pub fn len(&self) -> usize {
+        self.with_caches(|c| c.cache.len()).into_iter().sum()
+    }
+
+    /// Check if all the registered caches are empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the total weight of the entries in the registered caches.
+    pub fn weight(&self) -> u64 {
+        self.with_caches(|c| c.cache.weight()).into_iter().sum()
+    }
+
+    /// Get the maximum total weight shared by the caches, if any.
+    pub fn max_weight(&self) -> Option<u64> {
+        self.pool.as_ref().map(CapacityPool::max_weight)
+    }
+
+    /// Clear all the registered caches.
+    pub fn clear(&self) {
+        for cache in self.with_caches(|c| c.cache.clone()) {
+            cache.clear();
+        }
+    }
+
+    /// Get a snapshot of the statistics of all the registered caches, summed.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> crate::CacheStats {
+        self.with_caches(|c| c.cache.stats()).into_iter().sum()
+    }
+
+    /// Get a snapshot of the statistics of each registered cache, with its name.
+    #[cfg(feature = "stats")]
+    pub fn stats_by_cache(&self) -> Vec<(String, crate::CacheStats)> {
+        self.with_caches(|c| (c.name.clone(), c.cache.stats()))
+    }
+
+    /// Map the registered caches with `f`, without holding the lock afterwards.
+    fn with_caches<T>(&self, f: impl FnMut(&Registered) -> T) -> Vec<T> {
+        let caches = self.caches.read().unwrap_or_else(PoisonError::into_inner);
+        caches.values().map(f).collect()
+    }
+}
+
+impl Default for CacheRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,291 @@
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicBool, AtomicIsize, AtomicPtr, AtomicUsize, Ordering};
+use std::ptr;
+#[cfg(not(loom))]
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicPtr, AtomicUsize, Ordering};
+
+use crossbeam_queue::SegQueue;
+use crossbeam_utils::Backoff;
+
+/// A bounded fifo queue whose head can be inspected before being popped.
+///
+/// `SegQueue` has no `peek`, so a head that must stay in the queue is parked
+/// in `head` and handed out again before anything left in `ring`. Only one thread
+/// inspects the head at a time, while pushes and pops never wait for it: a pop
+/// racing with an inspection takes the next value instead, so the queue is only
+/// roughly fifo under contention. It allocates its segments as it fills up and
+/// frees them as it's drained, so the memory of the queue follows its length rather
+/// than its capacity.
+pub(crate) struct Ringbuf<T> {
+    ring: SegQueue<T>,
+    // `SegQueue` is unbounded, so the capacity is enforced here.
+    capacity: usize,
+    // The parked head, or null.
+    head: AtomicPtr<T>,
+    // Set by the thread inspecting the head, which has taken it out of the queue.
+    peeking: AtomicBool,
+    // Number of slots in use, including the parked head and pushes in flight.
+    reserved: AtomicUsize,
+    // Number of values in the queue. It's only incremented once a value is pushed,
+    // so it may briefly go below zero when the value is popped first.
+    len: AtomicIsize,
+}
+
+impl<T> Ringbuf<T> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            ring: SegQueue::new(),
+            capacity,
+            head: AtomicPtr::new(ptr::null_mut()),
+            peeking: AtomicBool::new(false),
+            reserved: AtomicUsize::new(0),
+            len: AtomicIsize::new(0),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire).max(0) as usize
+    }
+
+    /// Push a value to the tail, or give it back if the queue is full.
+    pub(crate) fn push(&self, value: T) -> Result<(), T> {
+        if self.reserved.fetch_add(1, Ordering::AcqRel) >= self.capacity {
+            self.reserved.fetch_sub(1, Ordering::AcqRel);
+            return Err(value);
+        }
+        self.ring.push(value);
+        self.len.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// Pop the head of the queue.
+    ///
+    /// If the only values left are being inspected by another thread, this waits
+    /// for it to put them back or pop them.
+    pub(crate) fn pop(&self) -> Option<T> {
+        let backoff = Backoff::new();
+        loop {
+            if let Some(value) = self.take_head().map(|v| *v).or_else(|| self.ring.pop()) {
+                self.release();
+                return Some(value);
+            }
+            if !self.peeking.load(Ordering::Acquire) {
+                // The head may have been parked again just before.
+                let value = self.take_head()?;
+                self.release();
+                return Some(*value);
+            }
+            snooze(&backoff);
+        }
+    }
+
+    /// Pop the head of the queue only if `f` returns true for it, waiting for the
+    /// other thread inspecting it if there is one.
+    pub(crate) fn pop_if(&self, f: impl FnOnce(&T) -> bool) -> Option<T> {
+        let backoff = Backoff::new();
+        loop {
+            if let Some(peek) = Peek::start(self) {
+                return peek.pop_if(f);
+            }
+            snooze(&backoff);
+        }
+    }
+
+    /// Pop the head of the queue only if `f` returns true for it, like `pop_if`, or
+    /// give up without calling `f` if another thread is inspecting it.
+    ///
+    /// This is for the inspections done on the way of writes, which can do without.
+    pub(crate) fn try_pop_if(&self, f: impl FnOnce(&T) -> bool) -> Option<T> {
+        Peek::start(self)?.pop_if(f)
+    }
+
+    /// Take the parked head, if any.
+    fn take_head(&self) -> Option<Box<T>> {
+        // Check first to avoid writing to the cache line when nothing is parked.
+        if self.head.load(Ordering::Acquire).is_null() {
+            return None;
+        }
+        let head = self.head.swap(ptr::null_mut(), Ordering::AcqRel);
+        // SAFETY: a non-null head was boxed when it was parked, and it's owned by
+        // the thread which swapped it out.
+        (!head.is_null()).then(|| unsafe { Box::from_raw(head) })
+    }
+
+    /// Account for a popped value.
+    fn release(&self) {
+        self.len.fetch_sub(1, Ordering::AcqRel);
+        self.reserved.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl<T> Drop for Ringbuf<T> {
+    fn drop(&mut self) {
+        drop(self.take_head());
+    }
+}
+
+/// The inspection of the head of a [`Ringbuf`], by the only thread allowed to.
+struct Peek<'a, T> {
+    ringbuf: &'a Ringbuf<T>,
+}
+
+impl<'a, T> Peek<'a, T> {
+    fn start(ringbuf: &'a Ringbuf<T>) -> Option<Self> {
+        ringbuf
+            .peeking
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+            .ok()?;
+        Some(Self { ringbuf })
+    }
+
+    fn pop_if(self, f: impl FnOnce(&T) -> bool) -> Option<T> {
+        let ringbuf = self.ringbuf;
+        let head = ringbuf
+            .take_head()
+            .or_else(|| ringbuf.ring.pop().map(Box::new))?;
+        if f(&head) {
+            ringbuf.release();
+            return Some(*head);
+        }
+        // Only the inspecting thread parks values, so nothing was parked meanwhile.
+        ringbuf.head.store(Box::into_raw(head), Ordering::Release);
+        None
+    }
+}
+
+impl<T> Drop for Peek<'_, T> {
+    fn drop(&mut self) {
+        self.ringbuf.peeking.store(false, Ordering::Release);
+    }
+}
+
+/// Wait for another thread to be done with the head.
+fn snooze(backoff: &Backoff) {
+    #[cfg(loom)]
+    {
+        let _ = backoff;
+        loom::thread::yield_now();
+    }
+    #[cfg(not(loom))]
+    backoff.snooze();
+}
+
+#[cfg(all(test, loom))]
+mod tests {
+    use loom::{sync::Arc, thread};
+
+    use super::Ringbuf;
+
+    #[test]
+    fn push_never_exceeds_capacity() {
+        loom::model(|| {
+            let ringbuf = Arc::new(Ringbuf::new(1));
+            let other = Arc::clone(&ringbuf);
+            let t = thread::spawn(move || other.push(1).is_ok());
+            let pushed = ringbuf.push(2).is_ok();
+            let other_pushed = t.join().unwrap();
+            assert!(pushed ^ other_pushed);
+            assert_eq!(ringbuf.len(), 1);
+        });
+    }
+
+    #[test]
+    fn parked_head_is_not_lost() {
+        loom::model(|| {
+            let ringbuf = Arc::new(Ringbuf::new(2));
+            ringbuf.push(1).unwrap();
+            let other = Arc::clone(&ringbuf);
+            // Parks the head without popping it, while another value is pushed.
+            let t = thread::spawn(move || other.pop_if(|_| false));
+            ringbuf.push(2).unwrap();
+            assert_eq!(t.join().unwrap(), None);
+            assert_eq!(ringbuf.len(), 2);
+            assert_eq!(ringbuf.pop(), Some(1));
+            assert_eq!(ringbuf.pop(), Some(2));
+            assert_eq!(ringbuf.pop(), None);
+            assert_eq!(ringbuf.len(), 0);
+        });
+    }
+
+    #[test]
+    fn concurrent_pops_take_each_value_once() {
+        loom::model(|| {
+            let ringbuf = Arc::new(Ringbuf::new(2));
+            ringbuf.push(1).unwrap();
+            ringbuf.push(2).unwrap();
+            let other = Arc::clone(&ringbuf);
+            let t = thread::spawn(move || other.pop_if(|v| *v == 1));
+            let popped = ringbuf.pop();
+            let other_popped = t.join().unwrap();
+            let mut values: Vec<_> = popped.into_iter().chain(other_popped).collect();
+            values.extend(ringbuf.pop());
+            values.sort_unstable();
+            assert_eq!(values, [1, 2]);
+            assert_eq!(ringbuf.len(), 0);
+        });
+    }
+
+    #[test]
+    fn contended_peek_gives_up_without_losing_the_head() {
+        loom::model(|| {
+            let ringbuf = Arc::new(Ringbuf::new(2));
+            ringbuf.push(1).unwrap();
+            let other = Arc::clone(&ringbuf);
+            let t = thread::spawn(move || other.pop_if(|_| false));
+            let popped = ringbuf.try_pop_if(|_| true);
+            assert_eq!(t.join().unwrap(), None);
+            let mut values: Vec<_> = popped.into_iter().collect();
+            values.extend(ringbuf.pop());
+            assert_eq!(values, [1]);
+            assert_eq!(ringbuf.len(), 0);
+        });
+    }
+
+    #[test]
+    fn pop_during_peek_waits_for_the_head() {
+        loom::model(|| {
+            let ringbuf = Arc::new(Ringbuf::new(1));
+            ringbuf.push(1).unwrap();
+            let other = Arc::clone(&ringbuf);
+            let t = thread::spawn(move || other.pop_if(|_| false));
+            // The peek only takes the value out to put it back.
+            assert_eq!(ringbuf.pop(), Some(1));
+            assert_eq!(t.join().unwrap(), None);
+            assert_eq!(ringbuf.len(), 0);
+        });
+    }
+
+    #[test]
+    fn len_only_counts_pushed_values() {
+        loom::model(|| {
+            let ringbuf = Arc::new(Ringbuf::new(1));
+            let other = Arc::clone(&ringbuf);
+            let t = thread::spawn(move || other.push(1).unwrap());
+            // A value counted in the length can be popped.
+            if ringbuf.len() > 0 {
+                assert_eq!(ringbuf.pop(), Some(1));
+            }
+            t.join().unwrap();
+            ringbuf.pop();
+            assert_eq!(ringbuf.len(), 0);
+            ringbuf.push(2).unwrap();
+        });
+    }
+
+    #[test]
+    fn pop_frees_a_slot_for_push() {
+        loom::model(|| {
+            let ringbuf = Arc::new(Ringbuf::new(1));
+            ringbuf.push(1).unwrap();
+            let other = Arc::clone(&ringbuf);
+            let t = thread::spawn(move || other.pop());
+            let pushed = ringbuf.push(2);
+            assert_eq!(t.join().unwrap(), Some(1));
+            // The push can only fail if it ran before the pop.
+            if pushed.is_ok() {
+                assert_eq!(ringbuf.pop(), Some(2));
+            }
+            assert_eq!(ringbuf.len(), 0);
+        });
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash, Hasher},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{Cache, ShardedCache, Value};
+
+/// A key in a named scope of a [`Cache`] shared by several subsystems, see
+/// [`Cache::scope`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ScopedKey<K> {
+    scope: Arc<str>,
+    key: K,
+}
+
+impl<K> ScopedKey<K> {
+    /// Create a key in the given scope.
+    pub fn new(scope: impl Into<Arc<str>>, key: K) -> Self {
+        Self {
+            scope: scope.into(),
+            key,
+        }
+    }
+
+    /// Get the name of the scope of the key.
+    pub fn scope(&self) -> &str {
+        &self.scope
+    }
+
+    /// Get the key within its scope.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Consumes the `ScopedKey` and returns the key within its scope.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+}
+
+/// A scope and a key, either owned by a [`ScopedKey`] or borrowed, so that a
+/// [`Scope`] can look up its keys without cloning them.
+trait ScopedKeyRef<K> {
+    fn scope(&self) -> &str;
+    fn key(&self) -> &K;
+}
+
+impl<K> ScopedKeyRef<K> for ScopedKey<K> {
+    fn scope(&self) -> &str {
+        &self.scope
+    }
+
+    fn key(&self) -> &K {
+        &self.key
+    }
+}
+
+impl<K> ScopedKeyRef<K> for (&str, &K) {
+    fn scope(&self) -> &str {
+        self.0
+    }
+
+    fn key(&self) -> &K {
+        self.1
+    }
+}
+
+impl<'a, K: 'a> Borrow<dyn ScopedKeyRef<K> + 'a> for ScopedKey<K> {
+    fn borrow(&self) -> &(dyn ScopedKeyRef<K> + 'a) {
+        self
+    }
+}
+
+// Hashes and compares the same as the derived implementations of `ScopedKey`.
+impl<K: Hash> Hash for dyn ScopedKeyRef<K> + '_ {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.scope().hash(state);
+        self.key().hash(state);
+    }
+}
+
+impl<K: PartialEq> PartialEq for dyn ScopedKeyRef<K> + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.scope() == other.scope() && self.key() == other.key()
+    }
+}
+
+impl<K: Eq> Eq for dyn ScopedKeyRef<K> + '_ {}
+
+/// A handle to a named scope of a [`Cache`], which wraps the keys it's given in a
+/// [`ScopedKey`] so that scopes never see each other's entries.
+///
+/// Obtained with [`Cache::scope`].
+pub struct Scope<'a, K, V, S = ahash::RandomState> {
+    target: &'a (dyn Target<K, V, S> + 'a),
+    name: Arc<str>,
+}
+
+/// What a [`Scope`] stores its entries in: a cache, or the shards of one.
+trait Target<K, V, S> {
+    /// Get the cache which the given key belongs to.
+    fn cache(&self, key: &dyn ScopedKeyRef<K>) -> &Cache<ScopedKey<K>, V, S>;
+
+    /// Discard all entries in the given scope.
+    fn invalidate_scope(&self, name: &str);
+}
+
+impl<K, V, S> Target<K, V, S> for Cache<ScopedKey<K>, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    fn cache(&self, _: &dyn ScopedKeyRef<K>) -> &Cache<ScopedKey<K>, V, S> {
+        self
+    }
+
+    fn invalidate_scope(&self, name: &str) {
+        self.invalidate_scope(name);
+    }
+}
+
+impl<K, V, S> Target<K, V, S> for ShardedCache<ScopedKey<K>, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    fn cache(&self, key: &dyn ScopedKeyRef<K>) -> &Cache<ScopedKey<K>, V, S> {
+        self.shard(key)
+    }
+
+    fn invalidate_scope(&self, name: &str) {
+        self.invalidate_scope(name);
+    }
+}
+
+impl<K, V, S> Cache<ScopedKey<K>, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    /// Get a handle to the scope with the given name.
+    ///
+    /// The scope is only a prefix of the keys, so it doesn't need to be created
+    /// first and all the scopes share the capacity of the cache.
+    pub fn scope(&self, name: &str) -> Scope<'_, K, V, S> {
+        Scope {
+            target: self,
+            name: name.into(),
+        }
+    }
+
+    /// Discard all entries in the scope with the given name, and leave the other
+    /// scopes alone.
+    ///
+    /// This scans the whole cache, see [`invalidate_where`](Self::invalidate_where).
+    pub fn invalidate_scope(&self, name: &str) {
+        self.invalidate_where(|key, _| *key.scope == *name);
+    }
+}
+
+impl<K, V, S> ShardedCache<ScopedKey<K>, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    /// See [`Cache::scope`]. The entries of a scope are spread over all the shards.
+    pub fn scope(&self, name: &str) -> Scope<'_, K, V, S> {
+        Scope {
+            target: self,
+            name: name.into(),
+        }
+    }
+
+    /// See [`Cache::invalidate_scope`].
+    pub fn invalidate_scope(&self, name: &str) {
+        self.invalidate_where(|key, _| *key.scope == *name);
+    }
+}
+
+impl<K, V, S> Scope<'_, K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    /// Get the name of the scope.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn key(&self, key: K) -> ScopedKey<K> {
+        ScopedKey {
+            scope: Arc::clone(&self.name),
+            key,
+        }
+    }
+
+    fn cache(&self, key: &dyn ScopedKeyRef<K>) -> &Cache<ScopedKey<K>, V, S> {
+        self.target.cache(key)
+    }
+
+    /// Insert a key-value pair in the scope, see [`Cache::insert`].
+    pub fn insert(&self, key: K, value: V) {
+        let key = self.key(key);
+        self.cache(&key).insert(key, value);
+    }
+
+    /// Insert a key-value pair in the scope with its own `ttl`, see
+    /// [`Cache::insert_with_ttl`].
+    pub fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) {
+        let key = self.key(key);
+        self.cache(&key).insert_with_ttl(key, value, ttl);
+    }
+
+    /// Check if the scope contains the given key, see [`Cache::contains_key`].
+    pub fn contains_key(&self, key: &K) -> bool {
+        let key: &dyn ScopedKeyRef<K> = &(&*self.name, key);
+        self.cache(key).contains_key(key)
+    }
+
+    /// Remove the entry associated with the given key from the scope and return its
+    /// value, see [`Cache::remove`].
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let key: &dyn ScopedKeyRef<K> = &(&*self.name, key);
+        self.cache(key).remove(key)
+    }
+
+    /// Discard the entry associated with the given key from the scope.
+    pub fn invalidate(&self, key: &K) {
+        self.remove(key);
+    }
+
+    /// Discard all entries in the scope, see [`Cache::invalidate_scope`].
+    pub fn invalidate_all(&self) {
+        self.target.invalidate_scope(&self.name);
+    }
+}
+
+impl<K, V, S> Scope<'_, K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    /// Get the value associated with the given key in the scope, see [`Cache::get`].
+    pub fn get(&self, key: &K) -> Option<Value<V>> {
+        let key: &dyn ScopedKeyRef<K> = &(&*self.name, key);
+        self.cache(key).get(key)
+    }
+
+    /// Get the value associated with the given key in the scope, or compute it with
+    /// `f` and insert it, see [`Cache::get_or_insert_with`].
+    pub fn get_or_insert_with(&self, key: K, f: impl FnOnce() -> V) -> Value<V> {
+        let key = self.key(key);
+        self.cache(&key).get_or_insert_with(key, f)
+    }
+}
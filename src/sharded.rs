@@ -0,0 +1,704 @@
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    sync::Arc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    janitor, refresh, Cache, CacheBuilder, CacheConfigError, CacheEntry, CacheEvent, CacheFull,
+    CacheResult, EntryMeta, Priority, SubscriptionId, Value, ValueRef,
+};
+
+/// A cache split into several independent [`Cache`]s, which a key is assigned to
+/// by its hash.
+///
+/// Every shard has its own ringbuf and does its own expiration, so this scales
+/// better than a single cache when many threads insert at the same time, at the
+/// cost of evicting per shard: a key may be evicted while other shards still have
+/// room. Build one with [`CacheBuilder::build_sharded`].
+///
+/// It has the same API as [`Cache`], and each method either goes to the shard of
+/// its key or to all the shards in turn.
+pub struct ShardedCache<K, V, S = ahash::RandomState> {
+    shards: Box<[Arc<Cache<K, V, S>>]>,
+    hasher: S,
+}
+
+impl<K, V> ShardedCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    /// Create a new cache with the given total capacity, ttl and number of shards.
+    pub fn new(capacity: usize, ttl: Duration, shards: usize) -> Self {
+        CacheBuilder::new()
+            .capacity(capacity)
+            .ttl(ttl)
+            .build_sharded(shards)
+    }
+
+    /// Create a new cache like [`new`](Self::new), or return an error if the
+    /// configuration is invalid, see [`CacheBuilder::try_build_sharded`].
+    pub fn try_new(
+        capacity: usize,
+        ttl: Duration,
+        shards: usize,
+    ) -> Result<Self, CacheConfigError> {
+        CacheBuilder::new()
+            .capacity(capacity)
+            .ttl(ttl)
+            .try_build_sharded(shards)
+    }
+
+    /// Create a builder for a sharded cache, see [`CacheBuilder::build_sharded`].
+    pub fn builder() -> CacheBuilder<K, V> {
+        CacheBuilder::new()
+    }
+}
+
+impl<K, V, S> ShardedCache<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    pub(crate) fn from_shards(shards: Vec<Cache<K, V, S>>, hasher: S) -> Self {
+        Self {
+            shards: shards.into_iter().map(Arc::new).collect(),
+            hasher,
+        }
+    }
+
+    /// Get the shard the given key belongs to.
+    ///
+    /// The key is hashed with the hasher of the shards, and the hash is mixed before
+    /// picking the shard, so that the keys of a shard don't share the bits its map
+    /// places them with.
+    pub fn shard<Q>(&self, key: &Q) -> &Arc<Cache<K, V, S>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = refresh::mix(self.hasher.hash_one(key));
+        &self.shards[(hash % self.shards.len() as u64) as usize]
+    }
+
+    /// Get all the shards.
+    pub fn shards(&self) -> &[Arc<Cache<K, V, S>>] {
+        &self.shards
+    }
+
+    /// Get the number of elements in the cache.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.len()).sum()
+    }
+
+    /// Check if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|s| s.is_empty())
+    }
+
+    /// See [`Cache::queue_len`].
+    pub fn queue_len(&self) -> usize {
+        self.shards.iter().map(|s| s.queue_len()).sum()
+    }
+
+    /// Get the total capacity of the cache.
+    pub fn capacity(&self) -> usize {
+        self.shards.iter().map(|s| s.capacity()).sum()
+    }
+
+    /// See [`Cache::is_disabled`].
+    pub fn is_disabled(&self) -> bool {
+        self.shards[0].is_disabled()
+    }
+
+    /// See [`Cache::name`]. All the shards have the same name.
+    pub fn name(&self) -> Option<&str> {
+        self.shards[0].name()
+    }
+
+    /// Get the total weight of the entries in the cache.
+    pub fn weight(&self) -> u64 {
+        self.shards.iter().map(|s| s.weight()).sum()
+    }
+
+    /// Get the maximum total weight of the entries in all the shards, if any.
+    pub fn max_weight(&self) -> Option<u64> {
+        self.shards.iter().map(|s| s.max_weight()).sum()
+    }
+
+    /// Estimate the memory used by all the shards, see
+    /// [`Cache::estimated_memory_usage`].
+    pub fn estimated_memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self
+                .shards
+                .iter()
+                .map(|s| s.estimated_memory_usage())
+                .sum::<usize>()
+    }
+
+    /// See [`Cache::with`].
+    pub fn with<R, Q>(&self, key: &Q, f: impl FnOnce(&V) -> R) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).with(key, f)
+    }
+
+    /// See [`Cache::get_with`].
+    pub fn get_with<R, Q>(&self, key: &Q, f: impl FnOnce(&V) -> R) -> Option<(R, EntryMeta)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).get_with(key, f)
+    }
+
+    /// See [`Cache::get_ref`].
+    pub fn get_ref<Q>(&self, key: &Q) -> Option<ValueRef<'_, K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).get_ref(key)
+    }
+
+    /// See [`Cache::modify`].
+    pub fn modify<R, Q>(&self, key: &Q, f: impl FnOnce(&mut V) -> R) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).modify(key, f)
+    }
+
+    /// See [`Cache::touch`].
+    pub fn touch<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).touch(key)
+    }
+
+    /// See [`Cache::extend_ttl`].
+    pub fn extend_ttl<Q>(&self, key: &Q, extra: Duration) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).extend_ttl(key, extra)
+    }
+
+    /// See [`Cache::ttl_remaining`].
+    pub fn ttl_remaining<Q>(&self, key: &Q) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).ttl_remaining(key)
+    }
+
+    /// See [`Cache::contains_key`].
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).contains_key(key)
+    }
+
+    /// Iterate over the keys in the cache, one shard after the other.
+    ///
+    /// See [`Cache::keys`].
+    pub fn keys(&self) -> impl Iterator<Item = K> + '_ {
+        self.shards.iter().flat_map(|s| s.keys())
+    }
+
+    /// See [`Cache::insert`].
+    pub fn insert(&self, key: K, value: V) {
+        self.shard(&key).insert(key, value);
+    }
+
+    /// See [`Cache::replace`].
+    pub fn replace(&self, key: K, value: V) -> Option<V> {
+        self.shard(&key).replace(key, value)
+    }
+
+    /// See [`Cache::insert_with_ttl`].
+    pub fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) {
+        self.shard(&key).insert_with_ttl(key, value, ttl);
+    }
+
+    /// See [`Cache::insert_with_priority`].
+    pub fn insert_with_priority(&self, key: K, value: V, priority: Priority) {
+        self.shard(&key).insert_with_priority(key, value, priority);
+    }
+
+    /// See [`Cache::insert_many`].
+    pub fn insert_many(&self, entries: impl IntoIterator<Item = (K, V)>) {
+        let now = self.shards[0].now();
+        for (key, value) in entries {
+            let shard = self.shard(&key);
+            let expire_at = shard.ttl_deadline(&key, now);
+            shard.insert_entry(key, value, now, expire_at, None, None);
+        }
+        self.shards.iter().for_each(|s| s.do_expire(now));
+    }
+
+    /// See [`Cache::warm`].
+    pub fn warm(&self, entries: impl IntoIterator<Item = (K, V)>, jitter: Duration) {
+        let now = self.shards[0].now();
+        for (key, value) in entries {
+            let shard = self.shard(&key);
+            let expire_at = shard.jittered_deadline(&key, now, jitter);
+            shard.insert_entry(key, value, now, expire_at, None, None);
+        }
+        self.shards.iter().for_each(|s| s.do_expire(now));
+    }
+
+    /// Push the buffered inserts of every shard to its ringbuf, see
+    /// [`Cache::flush_writes`].
+    pub fn flush_writes(&self) {
+        self.shards.iter().for_each(|s| s.flush_writes());
+    }
+
+    /// See [`Cache::insert_evicting`]. Only entries of the shard of the key are
+    /// evicted.
+    pub fn insert_evicting(&self, key: K, value: V) -> Vec<(K, V)> {
+        self.shard(&key).insert_evicting(key, value)
+    }
+
+    /// See [`Cache::insert_if`].
+    pub fn insert_if(&self, key: K, value: V, cond: impl FnOnce(Option<&V>) -> bool) -> bool {
+        self.shard(&key).insert_if(key, value, cond)
+    }
+
+    /// See [`Cache::try_insert`].
+    pub fn try_insert(&self, key: K, value: V) -> Result<(), CacheFull<K, V>> {
+        self.shard(&key).try_insert(key, value)
+    }
+
+    /// See [`Cache::compare_and_insert`].
+    pub fn compare_and_insert(&self, key: K, value: V, expected: u64) -> Result<u64, V> {
+        self.shard(&key).compare_and_insert(key, value, expected)
+    }
+
+    /// See [`Cache::insert_expiring_at`].
+    pub fn insert_expiring_at(&self, key: K, value: V, deadline: Instant) {
+        self.shard(&key).insert_expiring_at(key, value, deadline);
+    }
+
+    /// See [`Cache::entry`].
+    pub fn entry(&self, key: K) -> CacheEntry<'_, K, V, S> {
+        self.shard(&key).entry(key)
+    }
+
+    /// See [`Cache::remove`].
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).remove(key)
+    }
+
+    /// See [`Cache::remove_many`].
+    pub fn remove_many<'a, Q>(&self, keys: impl IntoIterator<Item = &'a Q>) -> Vec<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized + 'a,
+    {
+        keys.into_iter().map(|key| self.remove(key)).collect()
+    }
+
+    /// See [`Cache::invalidate`].
+    pub fn invalidate<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).invalidate(key);
+    }
+
+    /// See [`Cache::invalidate_all`].
+    pub fn invalidate_all(&self) {
+        self.clear();
+    }
+
+    /// See [`Cache::invalidate_where`].
+    pub fn invalidate_where(&self, pred: impl Fn(&K, &V) -> bool) -> usize {
+        self.shards.iter().map(|s| s.invalidate_where(&pred)).sum()
+    }
+
+    /// See [`Cache::retain`].
+    pub fn retain(&self, f: impl Fn(&K, &V) -> bool) {
+        self.shards.iter().for_each(|s| s.retain(&f));
+    }
+
+    /// Sweep every shard with [`Cache::sweep_expired`], and return how many entries
+    /// were removed in total.
+    pub fn sweep_expired(&self, max_items: usize) -> usize {
+        self.shards.iter().map(|s| s.sweep_expired(max_items)).sum()
+    }
+
+    /// See [`Cache::evict_expired`].
+    pub fn evict_expired(&self) {
+        self.shards.iter().for_each(|s| s.evict_expired());
+    }
+
+    /// Remove an expired entry from the head of the queue of a shard, trying them one
+    /// after the other, see [`Cache::pop_expired`].
+    pub fn pop_expired(&self) -> Option<(K, V, Instant)> {
+        self.shards.iter().find_map(|s| s.pop_expired())
+    }
+
+    /// Remove the expired entries from the heads of the queues one by one, as with
+    /// [`pop_expired`](Self::pop_expired), see [`Cache::drain_expired`].
+    pub fn drain_expired(&self) -> impl Iterator<Item = (K, V)> + '_ {
+        std::iter::from_fn(|| self.pop_expired().map(|(k, v, _)| (k, v)))
+    }
+
+    /// Remove all entries in the cache, one shard after the other.
+    ///
+    /// See [`Cache::clear`].
+    pub fn clear(&self) {
+        self.shards.iter().for_each(|s| s.clear());
+    }
+
+    /// See [`Cache::epoch`].
+    pub fn epoch(&self) -> u64 {
+        self.shards.iter().map(|s| s.epoch()).max().unwrap_or(0)
+    }
+
+    /// Invalidate the entries of all the shards, see [`Cache::invalidate_before`].
+    pub fn invalidate_before(&self, epoch: u64) -> bool {
+        let mut moved = false;
+        for shard in self.shards.iter() {
+            moved |= shard.invalidate_before(epoch);
+        }
+        moved
+    }
+
+    /// Subscribe to the mutations of all the shards, see [`Cache::subscribe`].
+    pub fn subscribe(
+        &self,
+        f: impl Fn(&CacheEvent<'_, K>) + Send + Sync + 'static,
+    ) -> SubscriptionId {
+        let f: crate::events::Subscriber<K> = Arc::new(f);
+        let id = self.shards[0].subscribers.add(None, Arc::clone(&f));
+        for shard in &self.shards[1..] {
+            shard.subscribers.add(Some(id), Arc::clone(&f));
+        }
+        SubscriptionId(id)
+    }
+
+    /// Remove a subscriber from all the shards, and return whether it was found.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut found = false;
+        for shard in self.shards.iter() {
+            found |= shard.subscribers.remove(id.0);
+        }
+        found
+    }
+}
+
+impl<K, V, S> ShardedCache<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    /// See [`Cache::get`].
+    pub fn get<Q>(&self, key: &Q) -> Option<Value<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).get(key)
+    }
+
+    /// See [`Cache::get_and_refresh`].
+    pub fn get_and_refresh<Q>(&self, key: &Q) -> Option<Value<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).get_and_refresh(key)
+    }
+
+    /// See [`Cache::get_and_refresh_with_ttl`].
+    pub fn get_and_refresh_with_ttl<Q>(&self, key: &Q, ttl: Duration) -> Option<Value<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).get_and_refresh_with_ttl(key, ttl)
+    }
+
+    /// See [`Cache::get_many`].
+    pub fn get_many<'a, Q>(&self, keys: impl IntoIterator<Item = &'a Q>) -> Vec<Option<Value<V>>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized + 'a,
+    {
+        let now = self.shards[0].now();
+        let values = keys
+            .into_iter()
+            .map(|key| self.shard(key).get_at(key, now))
+            .collect();
+        self.shards.iter().for_each(|s| s.expire_on_read(now));
+        values
+    }
+
+    /// See [`Cache::peek`].
+    pub fn peek<Q>(&self, key: &Q) -> Option<Value<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).peek(key)
+    }
+
+    /// Iterate over the entries in the cache, one shard after the other.
+    ///
+    /// See [`Cache::iter`].
+    pub fn iter(&self) -> impl Iterator<Item = (K, Value<V>)> + '_ {
+        self.shards.iter().flat_map(|s| s.iter())
+    }
+
+    /// Iterate over the values in the cache, one shard after the other.
+    ///
+    /// See [`Cache::values`].
+    pub fn values(&self) -> impl Iterator<Item = Value<V>> + '_ {
+        self.iter().map(|(_, v)| v)
+    }
+
+    /// Collect all entries in the cache.
+    pub fn snapshot(&self) -> Vec<(K, Value<V>)> {
+        self.iter().collect()
+    }
+
+    /// See [`Cache::get_or_insert_with`].
+    pub fn get_or_insert_with(&self, key: K, f: impl FnOnce() -> V) -> Value<V> {
+        self.shard(&key).get_or_insert_with(key, f)
+    }
+
+    /// See [`Cache::try_get_or_insert_with`].
+    pub fn try_get_or_insert_with<E>(
+        &self,
+        key: K,
+        f: impl FnOnce() -> Result<V, E>,
+    ) -> Result<Value<V>, E> {
+        self.shard(&key).try_get_or_insert_with(key, f)
+    }
+
+    /// Remove the oldest entry of the first shard which isn't empty, see
+    /// [`Cache::pop_oldest`].
+    ///
+    /// Entries come out oldest first within a shard, but not across shards.
+    pub fn pop_oldest(&self) -> Option<(K, Value<V>)> {
+        self.shards.iter().find_map(|s| s.pop_oldest())
+    }
+}
+
+impl<K, V, S> ShardedCache<K, Option<V>, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    /// See [`Cache::insert_negative`].
+    pub fn insert_negative(&self, key: K) {
+        self.shard(&key).insert_negative(key);
+    }
+}
+
+impl<K, V, S> ShardedCache<K, Option<V>, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    /// See [`Cache::lookup`].
+    pub fn lookup<Q>(&self, key: &Q) -> CacheResult<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).lookup(key)
+    }
+}
+
+impl<K, V, E, S> ShardedCache<K, Result<V, E>, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    E: Clone,
+    S: BuildHasher + Clone,
+{
+    /// See [`Cache::get_or_try_insert_with`].
+    pub fn get_or_try_insert_with(
+        &self,
+        key: K,
+        f: impl FnOnce() -> Result<V, E>,
+    ) -> Result<Value<V>, E> {
+        self.shard(&key).get_or_try_insert_with(key, f)
+    }
+}
+
+impl<K, V, S> ShardedCache<K, V, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    S: BuildHasher + Clone + Send + Sync + 'static,
+{
+    /// Spawn a thread calling [`evict_expired`](Self::evict_expired) every
+    /// `interval`, see [`Cache::spawn_janitor`]. A single thread serves all the
+    /// shards.
+    pub fn spawn_janitor(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        janitor::spawn(self, interval, Self::evict_expired)
+    }
+}
+
+impl<K, V, S> ShardedCache<K, V, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+    S: BuildHasher + Clone + Send + Sync + 'static,
+{
+    /// See [`Cache::get_with_refresh`].
+    pub fn get_with_refresh(
+        &self,
+        key: K,
+        refresh: impl FnOnce() -> V + Send + 'static,
+    ) -> Value<V> {
+        self.shard(&key).get_with_refresh(key, refresh)
+    }
+
+    /// See [`Cache::get_fresh_or_stale`].
+    pub fn get_fresh_or_stale(
+        &self,
+        key: K,
+        budget: Duration,
+        loader: impl FnOnce() -> V + Send + 'static,
+    ) -> Option<Value<V>> {
+        self.shard(&key).get_fresh_or_stale(key, budget, loader)
+    }
+
+    /// See [`Cache::get_or_refresh`].
+    pub fn get_or_refresh(&self, key: K) -> Option<Value<V>> {
+        self.shard(&key).get_or_refresh(key)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<K, V, S> ShardedCache<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    /// See [`Cache::get_or_load`].
+    pub async fn get_or_load(
+        &self,
+        key: K,
+        loader: impl std::future::Future<Output = V>,
+    ) -> Value<V> {
+        self.shard(&key).get_or_load(key, loader).await
+    }
+
+    /// See [`Cache::try_get_or_load`].
+    pub async fn try_get_or_load<E>(
+        &self,
+        key: K,
+        loader: impl std::future::Future<Output = Result<V, E>>,
+    ) -> Result<Value<V>, E> {
+        self.shard(&key).try_get_or_load(key, loader).await
+    }
+}
+
+#[cfg(feature = "stats")]
+impl<K, V, S> ShardedCache<K, V, S> {
+    /// Get a snapshot of the statistics of the cache, summed over all the shards.
+    pub fn stats(&self) -> crate::CacheStats {
+        self.shards.iter().map(|s| s.stats()).sum()
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl<K, V, S> crate::prom::Metered for ShardedCache<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    fn len(&self) -> usize {
+        ShardedCache::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        ShardedCache::capacity(self)
+    }
+
+    fn stats(&self) -> crate::CacheStats {
+        ShardedCache::stats(self)
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl<K, V, S> ShardedCache<K, V, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    S: BuildHasher + Clone + Send + Sync + 'static,
+{
+    /// Register the metrics of the cache, summed over all the shards, see
+    /// [`Cache::register_metrics`].
+    pub fn register_metrics(
+        self: &Arc<Self>,
+        registry: &prometheus::Registry,
+        name: &str,
+    ) -> prometheus::Result<()> {
+        crate::prom::register(self, registry, name)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K, V, S> ShardedCache<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    /// Serialize the entries in the cache which aren't expired, see [`Cache::dump`].
+    ///
+    /// Entries are written one shard after the other, each in insertion order.
+    pub fn dump<Ser>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error>
+    where
+        Ser: serde::Serializer,
+        K: serde::Serialize,
+        V: serde::Serialize,
+    {
+        let entries: Vec<_> = self.shards.iter().flat_map(|s| s.dump_entries()).collect();
+        crate::persist::serialize_entries(serializer, &entries)
+    }
+
+    /// Insert the entries serialized with [`dump`](Self::dump), see
+    /// [`Cache::restore`].
+    pub fn restore<'de, D>(&self, deserializer: D) -> Result<(), D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        K: serde::Deserialize<'de>,
+        V: serde::Deserialize<'de>,
+    {
+        let now = self.shards[0].now();
+        for (key, value, expire_at) in crate::persist::deserialize_entries(deserializer, now)? {
+            self.shard(&key)
+                .insert_entry(key, value, now, expire_at, None, None);
+        }
+        self.shards.iter().for_each(|s| s.do_expire(now));
+        Ok(())
+    }
+}
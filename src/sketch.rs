@@ -0,0 +1,79 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+// Multipliers used to derive the position of a key's counter in each row.
+const SEEDS: [u64; 4] = [
+    0xc3a5_c85c_97cb_3127,
+    0xb492_b66f_be98_f273,
+    0x9ae1_6a3b_2f90_404f,
+    0xcbf2_9ce4_8422_2325,
+];
+
+/// A count-min sketch estimating how often keys are accessed, used by the TinyLFU
+/// admission policy.
+///
+/// Every `u64` packs 16 counters of 4 bits, and every key maps to one counter in each
+/// of 4 rows. All counters are halved once the number of increments reaches the
+/// sample size, so the estimates favor recent accesses.
+pub(crate) struct FrequencySketch {
+    table: Box<[AtomicU64]>,
+    mask: usize,
+    additions: AtomicUsize,
+    sample_size: usize,
+}
+
+impl FrequencySketch {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let len = capacity.max(8).next_power_of_two();
+        Self {
+            table: (0..len).map(|_| AtomicU64::new(0)).collect(),
+            mask: len - 1,
+            additions: AtomicUsize::new(0),
+            sample_size: capacity.max(1).saturating_mul(10),
+        }
+    }
+
+    /// Get the estimated number of accesses of the key with the given hash.
+    pub(crate) fn frequency(&self, hash: u64) -> u8 {
+        (0..SEEDS.len())
+            .map(|row| {
+                let (index, shift) = self.position(hash, row);
+                ((self.table[index].load(Ordering::Relaxed) >> shift) & 0xf) as u8
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Record an access of the key with the given hash.
+    pub(crate) fn increment(&self, hash: u64) {
+        for row in 0..SEEDS.len() {
+            let (index, shift) = self.position(hash, row);
+            // Counters saturate at 15.
+            let _ = self.table[index].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |w| {
+                ((w >> shift) & 0xf != 0xf).then(|| w + (1 << shift))
+            });
+        }
+        if self.additions.fetch_add(1, Ordering::Relaxed) + 1 >= self.sample_size {
+            self.reset();
+        }
+    }
+
+    /// Halve all counters.
+    fn reset(&self) {
+        self.additions
+            .store(self.sample_size / 2, Ordering::Relaxed);
+        for word in self.table.iter() {
+            let _ = word.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |w| {
+                Some((w >> 1) & 0x7777_7777_7777_7777)
+            });
+        }
+    }
+
+    /// Get the index in the table and the bit shift of the counter of a key in the
+    /// given row.
+    fn position(&self, hash: u64, row: usize) -> (usize, u32) {
+        let h = (hash ^ (hash >> 32)).wrapping_mul(SEEDS[row]);
+        let index = (h >> 32) as usize & self.mask;
+        let shift = ((h >> 8) & 0xf) as u32 * 4;
+        (index, shift)
+    }
+}
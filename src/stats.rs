@@ -0,0 +1,168 @@
+#[cfg(feature = "stats")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(feature = "stats")]
+use crossbeam_utils::CachePadded;
+
+/// A snapshot of the statistics of a [`Cache`](crate::Cache).
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups which returned a value that was not expired.
+    pub hits: u64,
+    /// Lookups which returned nothing.
+    pub misses: u64,
+    /// Lookups which returned an expired value.
+    pub stale_hits: u64,
+    /// Values inserted in the cache.
+    pub insertions: u64,
+    /// Entries removed because they were expired.
+    pub expirations: u64,
+    /// Entries removed because the cache was full.
+    pub evictions: u64,
+}
+
+#[cfg(feature = "stats")]
+impl CacheStats {
+    /// Get the total number of lookups.
+    pub fn lookups(&self) -> u64 {
+        self.hits + self.misses + self.stale_hits
+    }
+
+    /// Get the ratio of lookups which returned a value that was not expired.
+    ///
+    /// Returns `0.0` if there was no lookup yet.
+    pub fn hit_rate(&self) -> f64 {
+        match self.lookups() {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+#[cfg(feature = "stats")]
+impl std::ops::Add for CacheStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            hits: self.hits + other.hits,
+            misses: self.misses + other.misses,
+            stale_hits: self.stale_hits + other.stale_hits,
+            insertions: self.insertions + other.insertions,
+            expirations: self.expirations + other.expirations,
+            evictions: self.evictions + other.evictions,
+        }
+    }
+}
+
+#[cfg(feature = "stats")]
+impl std::iter::Sum for CacheStats {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), |a, b| a + b)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Counter {
+    Hit,
+    Miss,
+    StaleHit,
+    Insertion,
+    Expiration,
+    Eviction,
+}
+
+/// The counters behind [`CacheStats`] and the `metrics` facade, which compile to
+/// nothing unless the `stats` or `metrics` feature is enabled.
+pub(crate) struct Counters {
+    #[cfg(feature = "stats")]
+    counters: [CachePadded<AtomicU64>; 6],
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+}
+
+impl Counters {
+    #[allow(unused_variables)]
+    pub(crate) fn new(name: Option<&str>, shard: Option<usize>) -> Self {
+        Self {
+            #[cfg(feature = "stats")]
+            counters: Default::default(),
+            #[cfg(feature = "metrics")]
+            metrics: Metrics::new(name, shard),
+        }
+    }
+
+    #[inline(always)]
+    #[allow(unused_variables)]
+    pub(crate) fn record(&self, counter: Counter) {
+        #[cfg(feature = "stats")]
+        self.counters[counter as usize].fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        self.metrics.counters[counter as usize].increment(1);
+    }
+
+    /// Record the number of entries in the cache.
+    #[inline(always)]
+    #[allow(unused_variables)]
+    pub(crate) fn record_len(&self, len: usize) {
+        #[cfg(feature = "metrics")]
+        self.metrics.len.set(len as f64);
+    }
+}
+
+#[cfg(feature = "stats")]
+impl Counters {
+    pub(crate) fn snapshot(&self) -> CacheStats {
+        let get = |counter: Counter| self.counters[counter as usize].load(Ordering::Relaxed);
+        CacheStats {
+            hits: get(Counter::Hit),
+            misses: get(Counter::Miss),
+            stale_hits: get(Counter::StaleHit),
+            insertions: get(Counter::Insertion),
+            expirations: get(Counter::Expiration),
+            evictions: get(Counter::Eviction),
+        }
+    }
+}
+
+/// The handles of the metrics of a cache, registered when it's built.
+#[cfg(feature = "metrics")]
+struct Metrics {
+    // Indexed by `Counter`.
+    counters: [metrics::Counter; 6],
+    len: metrics::Gauge,
+}
+
+#[cfg(feature = "metrics")]
+impl Metrics {
+    fn new(name: Option<&str>, shard: Option<usize>) -> Self {
+        let mut labels = Vec::new();
+        if let Some(name) = name {
+            labels.push(metrics::Label::new("cache", name.to_owned()));
+        }
+        // Shards are told apart, as each of them has its own length.
+        if let Some(shard) = shard {
+            labels.push(metrics::Label::new("shard", shard.to_string()));
+        }
+        Self {
+            counters: [
+                metrics::counter!("fastcache.hits", labels.clone()),
+                metrics::counter!("fastcache.misses", labels.clone()),
+                metrics::counter!("fastcache.stale_hits", labels.clone()),
+                metrics::counter!("fastcache.insertions", labels.clone()),
+                metrics::counter!("fastcache.expirations", labels.clone()),
+                metrics::counter!("fastcache.evictions", labels.clone()),
+            ],
+            len: metrics::gauge!("fastcache.len", labels.clone()),
+        }
+    }
+}
+
+#[cfg(feature = "stats")]
+impl<K, V, S> crate::Cache<K, V, S> {
+    /// Get a snapshot of the statistics of the cache.
+    pub fn stats(&self) -> CacheStats {
+        self.stats.snapshot()
+    }
+}
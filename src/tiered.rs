@@ -0,0 +1,302 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use dashmap::DashMap;
+
+use crate::{Cache, CacheBuilder, RemovalCause, Value};
+
+/// A backing store behind a [`TieredCache`], such as a remote cache or a disk.
+pub trait Store<K, V> {
+    /// The error returned when the store fails.
+    type Error;
+
+    /// Load the value associated with the given key, if any.
+    fn load(&self, key: &K) -> Result<Option<V>, Self::Error>;
+
+    /// Save a value for the given key.
+    fn store(&self, key: &K, value: &V) -> Result<(), Self::Error>;
+
+    /// Delete the value associated with the given key.
+    fn delete(&self, key: &K) -> Result<(), Self::Error>;
+}
+
+/// An asynchronous backing store behind a [`TieredCache`], see [`Store`].
+#[cfg(feature = "async")]
+pub trait AsyncStore<K, V> {
+    /// The error returned when the store fails.
+    type Error;
+
+    /// Load the value associated with the given key, if any.
+    fn load(
+        &self,
+        key: &K,
+    ) -> impl std::future::Future<Output = Result<Option<V>, Self::Error>> + Send;
+
+    /// Save a value for the given key.
+    fn store(
+        &self,
+        key: &K,
+        value: &V,
+    ) -> impl std::future::Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Delete the value associated with the given key.
+    fn delete(&self, key: &K) -> impl std::future::Future<Output = Result<(), Self::Error>> + Send;
+}
+
+/// When a [`TieredCache`] writes values to its store.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum WritePolicy {
+    /// Values are written to the store when they're inserted, before the cache.
+    #[default]
+    WriteThrough,
+    /// Values are only written to the store when they're removed from the cache
+    /// because they expired or to make room, or on [`TieredCache::flush`].
+    ///
+    /// Writes are batched this way, but values which weren't written yet are lost if
+    /// the store fails when they're removed, or if the process exits.
+    WriteBack,
+}
+
+/// A two-tier cache: a [`Cache`] in front of a slower [`Store`].
+///
+/// Lookups which miss or find an expired value fall through to the store, and
+/// concurrent lookups of the same key only load it once, as with
+/// [`Cache::get_or_insert_with`].
+///
+/// Keys are hashed with `H`, the hasher of the builder of the cache.
+pub struct TieredCache<K, V, S, H = ahash::RandomState> {
+    cache: Cache<K, V, H>,
+    store: Arc<S>,
+    policy: WritePolicy,
+    // Keys whose value hasn't been written to the store yet, with write-back, with
+    // the number of the write which marked them, so that a flush doesn't unmark a
+    // key written again meanwhile.
+    dirty: Arc<DashMap<K, u64, H>>,
+    writes: AtomicU64,
+}
+
+/// The error of loading a value from the store.
+enum LoadError<E> {
+    NotFound,
+    Store(E),
+}
+
+impl<K, V, S, H> TieredCache<K, V, S, H>
+where
+    K: Eq + Hash + Clone,
+    H: BuildHasher + Clone,
+{
+    /// Get the cache in front of the store.
+    ///
+    /// Writing to it directly bypasses the store.
+    pub fn cache(&self) -> &Cache<K, V, H> {
+        &self.cache
+    }
+
+    /// Get the store behind the cache.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Get the write policy.
+    pub fn policy(&self) -> WritePolicy {
+        self.policy
+    }
+
+    /// Mark the given key as not written to the store yet.
+    fn mark_dirty(&self, key: &K) {
+        let write = self.writes.fetch_add(1, Ordering::Relaxed);
+        self.dirty.insert(key.clone(), write);
+    }
+}
+
+impl<K, V, S, H> TieredCache<K, V, S, H>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    S: Store<K, V> + Send + Sync + 'static,
+    H: BuildHasher + Clone + Send + Sync + 'static,
+{
+    /// Create a tiered cache from the builder of the cache and the store.
+    ///
+    /// With [`WritePolicy::WriteBack`], values are written back from the eviction
+    /// listener, which still calls the one set on the builder, if any.
+    pub fn new(mut builder: CacheBuilder<K, V, H>, store: S, policy: WritePolicy) -> Self {
+        let store = Arc::new(store);
+        let dirty = Arc::new(DashMap::with_hasher(builder.hasher.clone()));
+        if policy == WritePolicy::WriteBack {
+            let listener = builder.listener.take();
+            let store = Arc::clone(&store);
+            let dirty = Arc::clone(&dirty);
+            builder = builder.eviction_listener(move |key: &K, value: &V, cause| {
+                let evicted = matches!(cause, RemovalCause::Expired | RemovalCause::Size);
+                let write = dirty.get(key).map(|write| *write);
+                if let Some(write) = write.filter(|_| evicted) {
+                    // There is no one to report the error to.
+                    let _ = store.store(key, value);
+                    dirty.remove_if(key, |_, w| *w == write);
+                }
+                if let Some(listener) = &listener {
+                    listener(key, value, cause);
+                }
+            });
+        }
+        Self {
+            cache: builder.build(),
+            store,
+            policy,
+            dirty,
+            writes: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<K, V, S, H> TieredCache<K, V, S, H>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: Store<K, V>,
+    H: BuildHasher + Clone,
+{
+    /// Get the value associated with the given key, loading it from the store if it
+    /// isn't in the cache or is expired.
+    ///
+    /// Returns `None` if the store doesn't have it either.
+    pub fn get(&self, key: &K) -> Result<Option<Value<V>>, S::Error> {
+        let loaded =
+            self.cache
+                .try_get_or_insert_with(key.clone(), || match self.store.load(key) {
+                    Ok(Some(value)) => Ok(value),
+                    Ok(None) => Err(LoadError::NotFound),
+                    Err(e) => Err(LoadError::Store(e)),
+                });
+        match loaded {
+            Ok(value) => Ok(Some(value)),
+            Err(LoadError::NotFound) => Ok(None),
+            Err(LoadError::Store(e)) => Err(e),
+        }
+    }
+
+    /// Insert a key-value pair, writing it to the store according to the policy.
+    ///
+    /// With write-through, the value isn't inserted in the cache if the store fails.
+    pub fn insert(&self, key: K, value: V) -> Result<(), S::Error> {
+        match self.policy {
+            WritePolicy::WriteThrough => self.store.store(&key, &value)?,
+            // Mark it first, so it's written back even if it's evicted right away.
+            WritePolicy::WriteBack => self.mark_dirty(&key),
+        }
+        self.cache.insert(key, value);
+        Ok(())
+    }
+
+    /// Remove the value associated with the given key from both the cache and the
+    /// store, and return the one from the cache.
+    pub fn remove(&self, key: &K) -> Result<Option<V>, S::Error> {
+        self.dirty.remove(key);
+        let value = self.cache.remove(key);
+        self.store.delete(key)?;
+        Ok(value)
+    }
+
+    /// Write all the values which weren't written to the store yet, with write-back.
+    ///
+    /// Stops at the first error, keeping the remaining values to be written later.
+    /// Keys written again while they're flushed stay marked, so their new value is
+    /// written by the next flush.
+    pub fn flush(&self) -> Result<(), S::Error> {
+        let keys: Vec<(K, u64)> = self.dirty.iter().map(|e| (e.key().clone(), *e)).collect();
+        for (key, write) in keys {
+            if let Some(value) = self.cache.peek(&key) {
+                self.store.store(&key, value.get())?;
+            }
+            self.dirty.remove_if(&key, |_, w| *w == write);
+        }
+        Ok(())
+    }
+
+    /// Remove all entries from the cache, after flushing them to the store.
+    ///
+    /// The store is left untouched otherwise.
+    pub fn clear(&self) -> Result<(), S::Error> {
+        self.flush()?;
+        self.cache.clear();
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+impl<K, V, S, H> TieredCache<K, V, S, H>
+where
+    K: Eq + Hash + Clone,
+    H: BuildHasher + Clone,
+{
+    /// Create a tiered cache from the builder of the cache and an asynchronous
+    /// store, which values are written through to.
+    ///
+    /// Write-back isn't supported, as the eviction listener can't await the store.
+    pub fn new_async(builder: CacheBuilder<K, V, H>, store: S) -> Self
+    where
+        S: AsyncStore<K, V>,
+    {
+        let dirty = Arc::new(DashMap::with_hasher(builder.hasher.clone()));
+        Self {
+            cache: builder.build(),
+            store: Arc::new(store),
+            policy: WritePolicy::WriteThrough,
+            dirty,
+            writes: AtomicU64::new(0),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<K, V, S, H> TieredCache<K, V, S, H>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: AsyncStore<K, V>,
+    H: BuildHasher + Clone,
+{
+    /// Get the value associated with the given key like [`get`](Self::get), with an
+    /// asynchronous store.
+    pub async fn get_async(&self, key: &K) -> Result<Option<Value<V>>, S::Error> {
+        let loader = async {
+            match self.store.load(key).await {
+                Ok(Some(value)) => Ok(value),
+                Ok(None) => Err(LoadError::NotFound),
+                Err(e) => Err(LoadError::Store(e)),
+            }
+        };
+        match self.cache.try_get_or_load(key.clone(), loader).await {
+            Ok(value) => Ok(Some(value)),
+            Err(LoadError::NotFound) => Ok(None),
+            Err(LoadError::Store(e)) => Err(e),
+        }
+    }
+
+    /// Insert a key-value pair like [`insert`](Self::insert), with an asynchronous
+    /// store.
+    pub async fn insert_async(&self, key: K, value: V) -> Result<(), S::Error> {
+        match self.policy {
+            WritePolicy::WriteThrough => self.store.store(&key, &value).await?,
+            WritePolicy::WriteBack => self.mark_dirty(&key),
+        }
+        self.cache.insert(key, value);
+        Ok(())
+    }
+
+    /// Remove the value associated with the given key like [`remove`](Self::remove),
+    /// with an asynchronous store.
+    pub async fn remove_async(&self, key: &K) -> Result<Option<V>, S::Error> {
+        self.dirty.remove(key);
+        let value = self.cache.remove(key);
+        self.store.delete(key).await?;
+        Ok(value)
+    }
+}
@@ -0,0 +1,126 @@
+use std::time::Duration;
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+use crate::RemovalCause;
+
+/// Why a cache is evicting entries which aren't expired.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Pressure {
+    /// The queue is full.
+    Capacity,
+    /// The entries weigh more than the maximum weight.
+    Weight,
+}
+
+/// The `tracing` instrumentation of a cache, which compiles to nothing unless the
+/// `tracing` feature is enabled.
+pub(crate) struct Tracer {
+    #[cfg(feature = "tracing")]
+    name: Option<String>,
+    #[cfg(feature = "tracing")]
+    shard: Option<usize>,
+    #[cfg(feature = "tracing")]
+    slow_sweep: Option<Duration>,
+}
+
+/// A sweep or an eviction in progress, inside its span.
+pub(crate) struct Traced {
+    #[cfg(feature = "tracing")]
+    _span: tracing::span::EnteredSpan,
+    #[cfg(feature = "tracing")]
+    start: Instant,
+}
+
+impl Tracer {
+    #[allow(unused_variables)]
+    pub(crate) fn new(
+        name: Option<&str>,
+        shard: Option<usize>,
+        slow_sweep: Option<Duration>,
+    ) -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            name: name.map(str::to_owned),
+            #[cfg(feature = "tracing")]
+            shard,
+            #[cfg(feature = "tracing")]
+            slow_sweep,
+        }
+    }
+
+    /// Enter the span of an expiration sweep.
+    #[inline(always)]
+    pub(crate) fn sweep_started(&self) -> Traced {
+        Traced {
+            #[cfg(feature = "tracing")]
+            _span: tracing::debug_span!(
+                "fastcache.sweep",
+                cache = self.name.as_deref(),
+                shard = self.shard
+            )
+            .entered(),
+            #[cfg(feature = "tracing")]
+            start: Instant::now(),
+        }
+    }
+
+    /// Record the end of an expiration sweep which removed `removed` entries, with a
+    /// warning if it took longer than the threshold.
+    #[inline(always)]
+    #[allow(unused_variables)]
+    pub(crate) fn sweep_done(&self, sweep: Traced, removed: usize) {
+        #[cfg(feature = "tracing")]
+        {
+            let elapsed = sweep.start.elapsed();
+            if let Some(threshold) = self.slow_sweep.filter(|t| elapsed > *t) {
+                tracing::warn!(removed, ?elapsed, ?threshold, "slow expiration sweep");
+            } else if removed > 0 {
+                tracing::debug!(removed, ?elapsed, "expired entries swept");
+            }
+        }
+    }
+
+    /// Enter the span of the evictions needed to relieve the given pressure.
+    #[inline(always)]
+    #[allow(unused_variables)]
+    pub(crate) fn evict_started(&self, pressure: Pressure) -> Traced {
+        Traced {
+            #[cfg(feature = "tracing")]
+            _span: tracing::debug_span!(
+                "fastcache.evict",
+                cache = self.name.as_deref(),
+                shard = self.shard,
+                ?pressure
+            )
+            .entered(),
+            #[cfg(feature = "tracing")]
+            start: Instant::now(),
+        }
+    }
+
+    /// Record the end of the evictions, which popped `popped` slots off the queue.
+    #[inline(always)]
+    #[allow(unused_variables)]
+    pub(crate) fn evict_done(&self, evict: Traced, popped: usize) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            popped,
+            elapsed = ?evict.start.elapsed(),
+            "entries evicted under pressure"
+        );
+    }
+
+    /// Record the removal of an entry.
+    #[inline(always)]
+    #[allow(unused_variables)]
+    pub(crate) fn removed(&self, cause: RemovalCause) {
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            cache = self.name.as_deref(),
+            shard = self.shard,
+            ?cause,
+            "entry removed"
+        );
+    }
+}
@@ -0,0 +1,1315 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    convert::Infallible,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Barrier, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "async")]
+use std::{
+    future::{self, Future},
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+
+use fastcache::{
+    AdmissionPolicy, BackgroundTask, Cache, CacheRegistry, CacheResult, CapacityPool, Clock,
+    EvictionPolicy, LocalCache, MockClock, Priority, RemovalCause, ScopedKey, ShardedCache, Store,
+    TieredCache, WritePolicy,
+};
+
+const TTL: Duration = Duration::from_secs(60);
+
+#[test]
+fn panicking_loader_does_not_block_the_key() {
+    let cache = Cache::<u32, u32>::new(8, TTL);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        cache.get_or_insert_with(1, || panic!("loader failed"));
+    }));
+    assert!(result.is_err());
+    assert_eq!(*cache.get_or_insert_with(1, || 1), 1);
+}
+
+#[test]
+fn sweep_keeps_the_first_entry_alive() {
+    let clock = MockClock::new();
+    let cache = Cache::<u32, u32>::builder()
+        .capacity(2)
+        .ttl(Duration::from_secs(10))
+        .clock(clock.clone())
+        .build();
+    cache.insert(1, 1);
+    clock.advance(Duration::from_secs(5));
+    cache.insert(2, 2);
+    clock.advance(Duration::from_secs(6));
+    // Sweeps 1, and stops at 2 which must stay in the queue.
+    assert_eq!(cache.sweep_expired(usize::MAX), 1);
+    assert!(cache.get(&1).is_none());
+    assert!(cache.get(&2).is_some_and(|v| !v.is_expired()));
+    assert_eq!(cache.queue_len(), 1);
+    cache.insert(3, 3);
+    cache.insert(4, 4);
+    assert!(cache.get(&2).is_none());
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn updating_a_key_keeps_its_slot() {
+    let cache = Cache::<u32, u32>::new(3, TTL);
+    for key in 1..=3 {
+        cache.insert(key, key);
+    }
+    for value in 0..5 {
+        cache.insert(3, value);
+    }
+    assert_eq!(cache.replace(3, 30), Some(4));
+    assert_eq!(cache.len(), 3);
+    assert_eq!(cache.queue_len(), 3);
+    assert!((1..=3).all(|key| cache.contains_key(&key)));
+}
+
+#[test]
+fn growing_an_entry_evicts_to_fit() {
+    let cache = Cache::<u32, u32>::builder()
+        .capacity(8)
+        .ttl(TTL)
+        .weigher(|_, v| *v)
+        .max_weight(4)
+        .build();
+    cache.insert(1, 1);
+    cache.insert(2, 1);
+    drop(cache.entry(2).and_modify(|v| *v = 4));
+    assert_eq!(cache.weight(), 4);
+    assert!(!cache.contains_key(&1));
+    cache.modify(&2, |v| *v = 3);
+    cache.insert(3, 1);
+    assert_eq!(cache.weight(), 4);
+}
+
+#[test]
+fn buffered_inserts_keep_to_the_max_weight() {
+    let cache = Cache::<u32, u32>::builder()
+        .capacity(16)
+        .ttl(TTL)
+        .max_weight(3)
+        .write_buffer(8)
+        .build();
+    for key in 0..6 {
+        cache.insert(key, key);
+    }
+    assert!(cache.weight() <= 3);
+    assert!(cache.contains_key(&5));
+}
+
+#[test]
+fn entry_insert_keeps_the_slot_of_the_key() {
+    let cache = Cache::<u32, u32>::new(3, TTL);
+    for key in 1..=3 {
+        cache.insert(key, key);
+    }
+    for value in 0..5 {
+        assert_eq!(*cache.entry(3).insert(value), value);
+    }
+    assert_eq!(cache.len(), 3);
+    assert_eq!(cache.queue_len(), 3);
+    assert!((1..=3).all(|key| cache.contains_key(&key)));
+}
+
+#[test]
+fn insert_if_overwrites_in_place() {
+    let cache = Cache::<u32, u32>::new(3, TTL);
+    for key in 1..=3 {
+        cache.insert(key, key);
+    }
+    for value in 4..9 {
+        assert!(cache.insert_if(3, value, |old| old.is_some_and(|old| *old < value)));
+    }
+    assert!(!cache.insert_if(3, 1, |old| old.is_some_and(|old| *old < 1)));
+    assert_eq!(*cache.get(&3).unwrap(), 8);
+    assert_eq!(cache.len(), 3);
+    assert_eq!(cache.queue_len(), 3);
+    assert!((1..=3).all(|key| cache.contains_key(&key)));
+}
+
+#[test]
+fn sharded_subscribers_see_every_shard() {
+    let cache = ShardedCache::<u32, u32>::new(64, TTL, 4);
+    let events = Arc::new(AtomicUsize::new(0));
+    let seen = Arc::clone(&events);
+    let id = cache.subscribe(move |_| {
+        seen.fetch_add(1, Ordering::Relaxed);
+    });
+    cache.insert_many((0..16).map(|i| (i, i)));
+    assert_eq!(events.load(Ordering::Relaxed), 16);
+
+    assert!(cache.unsubscribe(id));
+    assert!(!cache.unsubscribe(id));
+    cache.insert(16, 16);
+    assert_eq!(events.load(Ordering::Relaxed), 16);
+}
+
+#[test]
+fn get_with_refresh_serves_the_stale_value_while_refreshing() {
+    let clock = MockClock::new();
+    let cache = Arc::new(
+        Cache::<u32, u32>::builder()
+            .capacity(8)
+            .ttl(TTL)
+            .clock(clock.clone())
+            .build(),
+    );
+    assert_eq!(*cache.get_with_refresh(1, || 1), 1);
+    assert!(!cache.get_with_refresh(1, || unreachable!()).is_expired());
+
+    clock.advance(TTL * 2);
+    let stale = cache.get_with_refresh(1, || 2);
+    assert!(stale.is_expired());
+    assert_eq!(*stale, 1);
+    for _ in 0..1000 {
+        if cache.get(&1).is_some_and(|v| !v.is_expired()) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(*cache.get(&1).unwrap(), 2);
+}
+
+#[test]
+fn rejected_background_loads_fall_back() {
+    let clock = MockClock::new();
+    let spawned = Arc::new(AtomicUsize::new(0));
+    let attempts = spawned.clone();
+    let cache = Arc::new(
+        Cache::<u32, u32>::builder()
+            .capacity(8)
+            .ttl(TTL)
+            .stale_ttl(TTL * 10)
+            .clock(clock.clone())
+            .spawner(move |task| {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                Err(task)
+            })
+            .build(),
+    );
+    cache.insert(1, 1);
+    clock.advance(TTL * 2);
+    // The refresh is dropped, and doesn't keep the key marked as refreshing.
+    assert_eq!(*cache.get_with_refresh(1, || 2), 1);
+    assert_eq!(*cache.get_with_refresh(1, || 2), 1);
+    assert_eq!(spawned.load(Ordering::Relaxed), 2);
+
+    let value = cache.get_fresh_or_stale(1, Duration::ZERO, || 3).unwrap();
+    assert!(!value.is_expired());
+    assert_eq!(*value, 3);
+}
+
+#[test]
+fn extending_the_ttl_saturates() {
+    let cache = Cache::<u32, u32>::new(8, TTL);
+    cache.insert(1, 1);
+    assert!(cache.extend_ttl(&1, Duration::MAX));
+    assert!(cache.extend_ttl(&1, Duration::MAX));
+    assert!(cache.ttl_remaining(&1).unwrap() > TTL);
+}
+
+#[test]
+fn refreshing_with_a_huge_ttl_saturates() {
+    let cache = Cache::<u32, u32>::new(8, TTL);
+    cache.insert(1, 1);
+    assert!(cache.get_and_refresh_with_ttl(&1, Duration::MAX).is_some());
+    assert!(cache.ttl_remaining(&1).unwrap() > TTL);
+}
+
+#[test]
+fn a_huge_cache_ttl_never_expires() {
+    let clock = MockClock::new();
+    let builder = || {
+        Cache::<u32, u32>::builder()
+            .capacity(8)
+            .ttl(Duration::MAX)
+            .clock(clock.clone())
+    };
+    let cache = builder().build();
+    let jittered = builder().ttl_jitter_fraction(1.0).build();
+    let local = builder().build_local();
+    cache.insert(1, 1);
+    jittered.insert(1, 1);
+    local.insert(1, 1);
+    assert_eq!(*local.get_or_insert_with(2, || 2), 2);
+    clock.advance(Duration::from_secs(10 * 365 * 24 * 60 * 60));
+    assert!(!cache.get(&1).unwrap().is_expired());
+    assert!(jittered.get(&1).is_some());
+    assert!(!local.get(&1).unwrap().is_expired());
+    assert!(!local.get(&2).unwrap().is_expired());
+}
+
+#[test]
+fn scopes_find_their_own_keys() {
+    let cache = Cache::<ScopedKey<String>, u32>::new(8, TTL);
+    let sharded = ShardedCache::<ScopedKey<String>, u32>::new(8, TTL, 4);
+    for (a, b) in [
+        (cache.scope("a"), cache.scope("b")),
+        (sharded.scope("a"), sharded.scope("b")),
+    ] {
+        a.insert("key".to_owned(), 1);
+        b.insert("key".to_owned(), 2);
+        let key = "key".to_owned();
+        assert_eq!(*a.get(&key).unwrap(), 1);
+        assert_eq!(a.remove(&key), Some(1));
+        assert!(!a.contains_key(&key));
+        assert!(b.contains_key(&key));
+    }
+}
+
+#[test]
+fn pinned_entries_stay_pinned_when_updated() {
+    let cache = Cache::<u32, u32>::new(2, TTL);
+    cache.insert_with_priority(1, 1, Priority::Pinned);
+    cache.insert(2, 2);
+    cache.insert(3, 3);
+    assert!(cache.contains_key(&1));
+
+    cache.insert(1, 10);
+    cache.insert(4, 4);
+    cache.insert(5, 5);
+    assert_eq!(*cache.get(&1).unwrap(), 10);
+
+    cache.insert_with_priority(1, 1, Priority::Normal);
+    cache.insert(6, 6);
+    cache.insert(7, 7);
+    assert!(!cache.contains_key(&1));
+}
+
+#[test]
+fn stale_readers_share_one_load() {
+    let clock = MockClock::new();
+    let cache = Arc::new(
+        Cache::<u32, u32>::builder()
+            .capacity(8)
+            .ttl(TTL)
+            .clock(clock.clone())
+            .build(),
+    );
+    cache.insert(1, 1);
+    clock.advance(TTL * 2);
+    let loads = Arc::new(AtomicUsize::new(0));
+    let barrier = Arc::new(Barrier::new(8));
+    let readers: Vec<_> = (0..8)
+        .map(|_| {
+            let (cache, loads, barrier) = (cache.clone(), loads.clone(), barrier.clone());
+            thread::spawn(move || {
+                barrier.wait();
+                let value = cache.get_fresh_or_stale(1, Duration::from_secs(10), move || {
+                    loads.fetch_add(1, Ordering::Relaxed);
+                    thread::sleep(Duration::from_millis(50));
+                    2
+                });
+                *value.unwrap()
+            })
+        })
+        .collect();
+    for reader in readers {
+        assert_eq!(reader.join().unwrap(), 2);
+    }
+    assert_eq!(loads.load(Ordering::Relaxed), 1);
+}
+
+/// A clock which jumps far ahead once told to, further than a `MockClock` can go.
+struct JumpingClock {
+    start: Instant,
+    jumped: Arc<AtomicBool>,
+}
+
+impl Clock for JumpingClock {
+    fn now(&self) -> Instant {
+        match self.jumped.load(Ordering::Relaxed) {
+            true => self.start + LONG,
+            false => self.start,
+        }
+    }
+}
+
+const LONG: Duration = Duration::from_secs(u64::MAX / 4);
+
+#[test]
+fn cached_errors_expire_at_most_a_century_away() {
+    let jumped = Arc::new(AtomicBool::new(false));
+    let cache = Cache::<u32, Result<u32, String>>::builder()
+        .capacity(8)
+        .ttl(TTL)
+        .error_ttl(LONG)
+        .clock(JumpingClock {
+            start: Instant::now(),
+            jumped: jumped.clone(),
+        })
+        .build();
+    jumped.store(true, Ordering::Relaxed);
+    let err = cache.get_or_try_insert_with(1, || Err("unavailable".to_owned()));
+    assert_eq!(err.err().as_deref(), Some("unavailable"));
+    assert!(cache.contains_key(&1));
+}
+
+#[test]
+fn local_cache_evicts_in_insertion_order() {
+    let cache = LocalCache::<u32, u32>::new(2, TTL);
+    cache.insert(1, 1);
+    cache.insert(2, 2);
+    cache.insert(1, 10);
+    assert_eq!(cache.remove(&2), Some(2));
+    cache.insert(3, 3);
+    cache.insert(4, 4);
+    assert!(!cache.contains_key(&1));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(*cache.get_or_insert_with(3, || 0), 3);
+}
+
+#[test]
+fn local_cache_reads_and_updates_in_place() {
+    let clock = MockClock::new();
+    let removed = Arc::new(Mutex::new(Vec::new()));
+    let cache = Cache::<u32, u32>::builder()
+        .capacity(8)
+        .ttl(TTL)
+        .clock(clock.clone())
+        .eviction_listener({
+            let removed = removed.clone();
+            move |k: &u32, _: &u32, cause| removed.lock().unwrap().push((*k, cause))
+        })
+        .build_local();
+    for key in 0..4 {
+        cache.insert(key, key);
+    }
+    assert_eq!(cache.modify(&1, |v| std::mem::replace(v, 10)), Some(1));
+    assert_eq!(*cache.peek(&1).unwrap(), 10);
+    assert_eq!(cache.modify(&9, |v| *v), None);
+    cache.retain(|k, _| k % 2 == 1);
+    assert_eq!(cache.len(), 2);
+    let err = cache.try_get_or_insert_with(4, || Err("unavailable"));
+    assert_eq!(err.err(), Some("unavailable"));
+    assert!(!cache.contains_key(&4));
+
+    clock.advance(TTL / 2);
+    assert!(cache.touch(&1));
+    assert_eq!(cache.ttl_remaining(&1), Some(TTL));
+    assert_eq!(cache.ttl_remaining(&3), Some(TTL / 2));
+    clock.advance(TTL / 2 + Duration::from_millis(1));
+    assert!(!cache.touch(&3));
+    let mut snapshot = cache.snapshot();
+    snapshot.sort_by_key(|(k, _)| *k);
+    assert_eq!(snapshot.len(), 2);
+    assert!(!snapshot[0].1.is_expired());
+    assert!(snapshot[1].1.is_expired());
+    assert!(cache.peek(&3).unwrap().is_expired());
+    cache.evict_expired();
+    assert!(cache.peek(&3).is_none());
+    removed.lock().unwrap().sort_by_key(|(k, _)| *k);
+    assert_eq!(
+        *removed.lock().unwrap(),
+        [
+            (0, RemovalCause::Explicit),
+            (2, RemovalCause::Explicit),
+            (3, RemovalCause::Expired),
+        ]
+    );
+}
+
+/// Poll a future to completion on the current thread.
+#[cfg(feature = "async")]
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::yield_now();
+    }
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn get_or_load_awaits_the_loader_once_per_ttl() {
+    let clock = MockClock::new();
+    let cache = Cache::<u32, u32>::builder()
+        .capacity(8)
+        .ttl(TTL)
+        .clock(clock.clone())
+        .build();
+    assert_eq!(*block_on(cache.get_or_load(1, async { 1 })), 1);
+    assert_eq!(*block_on(cache.get_or_load(1, async { 2 })), 1);
+    let err = block_on(cache.try_get_or_load(2, async { Err::<u32, _>("failed") }));
+    assert_eq!(err.err(), Some("failed"));
+    assert!(!cache.contains_key(&2));
+
+    clock.advance(TTL * 2);
+    assert_eq!(*block_on(cache.get_or_load(1, async { 3 })), 3);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn a_cancelled_load_lets_the_next_caller_load() {
+    let cache = Cache::<u32, u32>::new(8, TTL);
+    {
+        let mut pending = pin!(cache.get_or_load(1, future::pending()));
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(pending.as_mut().poll(&mut cx).is_pending());
+    }
+    assert_eq!(*block_on(cache.get_or_load(1, async { 2 })), 2);
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn stats_count_each_lookup_and_removal() {
+    let clock = MockClock::new();
+    let cache = Cache::<u32, u32>::builder()
+        .capacity(2)
+        .ttl(TTL)
+        .stale_ttl(TTL)
+        .clock(clock.clone())
+        .build();
+    assert_eq!(cache.stats().hit_rate(), 0.0);
+    cache.insert(1, 1);
+    cache.insert(2, 2);
+    cache.insert(3, 3);
+    assert!(cache.get(&1).is_none());
+    assert!(!cache.get(&2).unwrap().is_expired());
+    clock.advance(TTL + Duration::from_secs(1));
+    assert!(cache.get(&3).unwrap().is_expired());
+    clock.advance(TTL);
+    cache.evict_expired();
+    assert!(cache.is_empty());
+
+    let stats = cache.stats();
+    assert_eq!(
+        (stats.hits, stats.misses, stats.stale_hits),
+        (1, 1, 1),
+        "{stats:?}"
+    );
+    assert_eq!(
+        (stats.insertions, stats.evictions, stats.expirations),
+        (3, 1, 2),
+        "{stats:?}"
+    );
+    assert_eq!(stats.lookups(), 3);
+    assert!((stats.hit_rate() - 1.0 / 3.0).abs() < 1e-9);
+}
+
+#[test]
+fn lru_keeps_the_entries_which_were_read() {
+    for (policy, evicted) in [(EvictionPolicy::Fifo, 1), (EvictionPolicy::Lru, 2)] {
+        let cache = Cache::<u32, u32>::builder()
+            .capacity(3)
+            .ttl(TTL)
+            .eviction_policy(policy)
+            .build();
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        cache.insert(3, 3);
+        assert!(cache.get(&1).is_some());
+        // Peeking doesn't count as a use.
+        assert!(cache.peek(&2).is_some());
+        cache.insert(4, 4);
+        assert!(!cache.contains_key(&evicted), "{policy:?}");
+        assert_eq!(cache.len(), 3);
+    }
+}
+
+#[test]
+fn tiny_lfu_keeps_hot_entries_through_a_scan() {
+    let cache = Cache::<u32, u32>::builder()
+        .capacity(64)
+        .ttl(TTL)
+        .admission_policy(AdmissionPolicy::TinyLfu)
+        .build();
+    for key in 0..8 {
+        cache.insert(key, key);
+        for _ in 0..8 {
+            assert!(cache.get(&key).is_some());
+        }
+    }
+    for key in 8..64 {
+        cache.insert(key, key);
+    }
+    for key in 1000..1064 {
+        cache.insert(key, key);
+    }
+    assert!((0..8).all(|key| cache.contains_key(&key)));
+    assert!(!cache.contains_key(&1000));
+
+    // A key used more often than the victim is admitted.
+    for _ in 0..16 {
+        assert!(cache.get(&2000).is_none());
+    }
+    cache.insert(2000, 2000);
+    assert!(cache.contains_key(&2000));
+    assert_eq!(cache.len(), 64);
+}
+
+#[test]
+fn idle_entries_expire_unless_read() {
+    let clock = MockClock::new();
+    let tti = Duration::from_secs(10);
+    let cache = Cache::<u32, u32>::builder()
+        .capacity(8)
+        .ttl(TTL)
+        .time_to_idle(tti)
+        .clock(clock.clone())
+        .build();
+    cache.insert(1, 1);
+    cache.insert(2, 2);
+    for _ in 0..3 {
+        clock.advance(tti / 2);
+        assert!(!cache.get(&1).unwrap().is_expired());
+        // Peeking doesn't count as a read.
+        assert!(cache.peek(&2).is_some());
+    }
+    assert!(cache.get(&2).is_none_or(|v| v.is_expired()));
+    assert!(!cache.get(&1).unwrap().is_expired());
+    // The ttl still applies to entries which are read.
+    clock.advance(TTL);
+    assert!(cache.get(&1).is_none_or(|v| v.is_expired()));
+
+    let idle = Cache::<u32, u32>::builder()
+        .capacity(8)
+        .ttl(TTL)
+        .time_to_idle(Duration::MAX)
+        .clock(clock.clone())
+        .build();
+    idle.insert(1, 1);
+    assert!(!idle.get(&1).unwrap().is_expired());
+    clock.advance(TTL / 2);
+    assert!(!idle.get(&1).unwrap().is_expired());
+}
+
+#[test]
+fn the_janitor_evicts_expired_entries_of_an_idle_cache() {
+    let clock = MockClock::new();
+    let expired = Arc::new(AtomicUsize::new(0));
+    let counter = expired.clone();
+    let cache = Arc::new(
+        Cache::<u32, u32>::builder()
+            .capacity(8)
+            .ttl(TTL)
+            .eviction_listener(move |_, _, cause| {
+                assert_eq!(cause, RemovalCause::Expired);
+                counter.fetch_add(1, Ordering::Relaxed);
+            })
+            .clock(clock.clone())
+            .build(),
+    );
+    cache.insert(1, 1);
+    cache.insert_with_ttl(2, 2, TTL * 2);
+    // An entry with a shorter ttl stuck behind a live one is evicted too.
+    cache.insert_with_ttl(3, 3, TTL / 2);
+    clock.advance(TTL + Duration::from_secs(1));
+    cache.evict_expired();
+    assert_eq!(expired.load(Ordering::Relaxed), 2);
+    assert!(cache.contains_key(&2));
+
+    let janitor = cache.spawn_janitor(Duration::from_millis(1));
+    clock.advance(TTL);
+    for _ in 0..1000 {
+        if cache.is_empty() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert!(cache.is_empty());
+    assert_eq!(expired.load(Ordering::Relaxed), 3);
+    // The janitor stops once the cache is dropped.
+    drop(cache);
+    janitor.join().unwrap();
+}
+
+#[test]
+fn negative_entries_expire_after_their_own_ttl() {
+    let clock = MockClock::new();
+    let cache = Cache::<u32, Option<u32>>::builder()
+        .capacity(8)
+        .ttl(TTL)
+        .negative_ttl(TTL / 6)
+        .stale_ttl(TTL)
+        .clock(clock.clone())
+        .build();
+    cache.insert(1, Some(1));
+    cache.insert_negative(2);
+    assert!(matches!(cache.lookup(&1), CacheResult::Hit(v) if *v == 1));
+    assert!(matches!(cache.lookup(&2), CacheResult::NegativeHit));
+    assert!(matches!(cache.lookup(&3), CacheResult::Miss));
+
+    clock.advance(TTL / 6 + Duration::from_secs(1));
+    assert!(matches!(cache.lookup(&2), CacheResult::Miss));
+    assert!(matches!(cache.lookup(&1), CacheResult::Hit(v) if !v.is_expired()));
+    // Unlike negative ones, expired values are still returned.
+    clock.advance(TTL);
+    assert!(matches!(cache.lookup(&1), CacheResult::Hit(v) if v.is_expired()));
+}
+
+/// A store keeping values in memory, which can be paused in the middle of a write.
+#[derive(Default)]
+struct PausingStore {
+    values: Mutex<HashMap<u32, u32>>,
+    // Waited on twice by a write: once it started, and before it returns.
+    pause: Mutex<Option<Arc<Barrier>>>,
+}
+
+impl Store<u32, u32> for PausingStore {
+    type Error = Infallible;
+
+    fn load(&self, key: &u32) -> Result<Option<u32>, Infallible> {
+        Ok(self.values.lock().unwrap().get(key).copied())
+    }
+
+    fn store(&self, key: &u32, value: &u32) -> Result<(), Infallible> {
+        self.values.lock().unwrap().insert(*key, *value);
+        let pause = self.pause.lock().unwrap().take();
+        if let Some(pause) = pause {
+            pause.wait();
+            pause.wait();
+        }
+        Ok(())
+    }
+
+    fn delete(&self, key: &u32) -> Result<(), Infallible> {
+        self.values.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+#[test]
+fn write_back_writes_evicted_and_flushed_values() {
+    let builder = Cache::builder().capacity(1).ttl(TTL);
+    let tiered = TieredCache::new(builder, PausingStore::default(), WritePolicy::WriteBack);
+    tiered.insert(1, 1).unwrap();
+    assert!(tiered.store().load(&1).unwrap().is_none());
+    tiered.insert(2, 2).unwrap();
+    assert_eq!(tiered.store().load(&1).unwrap(), Some(1));
+    assert_eq!(*tiered.get(&1).unwrap().unwrap(), 1);
+    assert_eq!(tiered.store().load(&2).unwrap(), Some(2));
+    assert!(tiered.get(&3).unwrap().is_none());
+}
+
+#[test]
+fn writes_during_a_flush_are_flushed_later() {
+    let builder = Cache::builder().capacity(8).ttl(TTL);
+    let tiered = TieredCache::new(builder, PausingStore::default(), WritePolicy::WriteBack);
+    tiered.insert(1, 1).unwrap();
+    let pause = Arc::new(Barrier::new(2));
+    *tiered.store().pause.lock().unwrap() = Some(pause.clone());
+    thread::scope(|s| {
+        let flush = s.spawn(|| tiered.flush().unwrap());
+        pause.wait();
+        tiered.insert(1, 2).unwrap();
+        pause.wait();
+        flush.join().unwrap();
+    });
+    assert_eq!(tiered.store().load(&1).unwrap(), Some(1));
+    tiered.flush().unwrap();
+    assert_eq!(tiered.store().load(&1).unwrap(), Some(2));
+}
+
+#[test]
+fn refresh_ahead_reloads_values_before_they_expire() {
+    let clock = MockClock::new();
+    let tasks = Arc::new(Mutex::new(Vec::<BackgroundTask>::new()));
+    let queued = tasks.clone();
+    let loads = Arc::new(AtomicUsize::new(0));
+    let counter = loads.clone();
+    let cache = Arc::new(
+        Cache::<u32, usize>::builder()
+            .capacity(8)
+            .ttl(TTL)
+            .refresh_after(TTL / 2)
+            .refresher(move |_| counter.fetch_add(1, Ordering::Relaxed) + 1)
+            .spawner(move |task| {
+                queued.lock().unwrap().push(task);
+                Ok(())
+            })
+            .clock(clock.clone())
+            .build(),
+    );
+    let run_tasks = || tasks.lock().unwrap().drain(..).for_each(|task| task());
+    // A missing value is loaded right away.
+    assert_eq!(*cache.get_or_refresh(1).unwrap(), 1);
+    clock.advance(TTL / 4);
+    assert_eq!(*cache.get_or_refresh(1).unwrap(), 1);
+    assert!(tasks.lock().unwrap().is_empty());
+
+    clock.advance(TTL / 4);
+    assert_eq!(*cache.get_or_refresh(1).unwrap(), 1);
+    // Only one refresh of a key is queued at a time.
+    assert_eq!(*cache.get_or_refresh(1).unwrap(), 1);
+    assert_eq!(tasks.lock().unwrap().len(), 1);
+    run_tasks();
+    assert_eq!(*cache.get(&1).unwrap(), 2);
+
+    // A reload is dropped if the key was written meanwhile.
+    clock.advance(TTL / 2);
+    assert_eq!(*cache.get_or_refresh(1).unwrap(), 2);
+    cache.insert(1, 10);
+    run_tasks();
+    assert_eq!(*cache.get(&1).unwrap(), 10);
+    assert_eq!(loads.load(Ordering::Relaxed), 3);
+}
+
+#[cfg(feature = "cached-compat")]
+#[test]
+fn the_cached_adapter_hides_expired_values() {
+    use cached::Cached;
+
+    let clock = MockClock::new();
+    let cache = Cache::<u32, u32>::builder()
+        .capacity(8)
+        .ttl(TTL)
+        .stale_ttl(TTL)
+        .clock(clock.clone())
+        .build();
+    let mut adapter = fastcache::CachedAdapter::new(cache);
+    assert_eq!(adapter.cache_set(1, 1), None);
+    *adapter.cache_get_mut(&1).unwrap() += 1;
+    assert_eq!(adapter.cache_get(&1), Some(&2));
+    adapter.cache_set(2, 2);
+    adapter.cache_set(3, 3);
+    assert_eq!(adapter.cache_remove_entry(&3), Some((3, 3)));
+
+    clock.advance(TTL + Duration::from_secs(1));
+    assert!(adapter.cache().get(&1).unwrap().is_expired());
+    assert_eq!(adapter.cache_get(&1), None);
+    assert_eq!(adapter.cache_remove(&1), None);
+    assert_eq!(adapter.cache_remove_entry(&2), None);
+    assert!(!adapter.cache().contains_key(&2));
+}
+
+#[test]
+fn evicted_and_expired_entries_are_handed_back() {
+    let clock = MockClock::new();
+    let cache = Cache::<u32, u32>::builder()
+        .capacity(2)
+        .ttl(TTL)
+        .clock(clock.clone())
+        .build();
+    assert!(cache.insert_evicting(1, 1).is_empty());
+    assert!(cache.insert_evicting(2, 2).is_empty());
+    assert_eq!(cache.insert_evicting(3, 3), [(1, 1)]);
+    // Updating a key doesn't evict anything.
+    assert!(cache.insert_evicting(2, 20).is_empty());
+    assert!(cache.pop_expired().is_none());
+
+    let expire_at = clock.now() + TTL;
+    clock.advance(TTL * 2);
+    assert_eq!(cache.pop_expired(), Some((2, 20, expire_at)));
+    assert_eq!(cache.pop_expired(), Some((3, 3, expire_at)));
+    assert_eq!(cache.pop_expired(), None);
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn sharded_and_tiered_caches_use_the_hasher_of_the_builder() {
+    let sharded: ShardedCache<u32, u32, RandomState> = Cache::builder()
+        .capacity(256)
+        .ttl(TTL)
+        .hasher(RandomState::new())
+        .build_sharded(4);
+    for key in 0..64 {
+        sharded.insert(key, key);
+    }
+    assert!((0..64).all(|key| sharded.get(&key).is_some_and(|v| *v == key)));
+    assert!(sharded.shards().iter().all(|shard| !shard.is_empty()));
+
+    let builder = Cache::builder()
+        .capacity(8)
+        .ttl(TTL)
+        .hasher(RandomState::new());
+    let tiered: TieredCache<u32, u32, _, RandomState> =
+        TieredCache::new(builder, PausingStore::default(), WritePolicy::WriteBack);
+    tiered.insert(1, 1).unwrap();
+    tiered.flush().unwrap();
+    assert_eq!(tiered.store().load(&1).unwrap(), Some(1));
+}
+
+#[cfg(feature = "read-mostly")]
+#[test]
+fn read_mostly_cache_serves_snapshots() {
+    let clock = MockClock::new();
+    let cache = Cache::<u32, u32>::builder()
+        .capacity(2)
+        .ttl(TTL)
+        .clock(clock.clone())
+        .build_read_mostly();
+    cache.insert_many([(1, 1), (2, 2)]);
+    // A reader keeps the snapshot it started with while the cache is written.
+    let seen = cache.with(&1, |v| {
+        cache.insert(1, 10);
+        *v
+    });
+    assert_eq!(seen, Some(1));
+    assert_eq!(*cache.get(&1).unwrap(), 10);
+
+    cache.insert(3, 3);
+    assert!(!cache.contains_key(&1));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.remove(&2), Some(2));
+
+    clock.advance(TTL + Duration::from_secs(1));
+    assert!(cache.get(&3).unwrap().is_expired());
+    assert!(!cache.contains_key(&3));
+    cache.insert(4, 4);
+    assert!(cache.get(&3).is_none());
+}
+
+#[test]
+fn ttl_jitter_spreads_out_expirations() {
+    let clock = MockClock::new();
+    let ttl = Duration::from_secs(100);
+    let cache = Cache::<u32, u32>::builder()
+        .capacity(128)
+        .ttl(ttl)
+        .ttl_jitter_fraction(0.5)
+        .clock(clock.clone())
+        .build();
+    for key in 0..64 {
+        cache.insert(key, key);
+    }
+    let mut remaining: Vec<_> = (0..64)
+        .map(|key| cache.ttl_remaining(&key).unwrap())
+        .collect();
+    remaining.sort();
+    assert!(remaining[0] >= ttl / 2);
+    assert!(remaining[63] <= ttl);
+    assert!(remaining[63] - remaining[0] > ttl / 4);
+
+    // The ttl given to a single write isn't jittered.
+    cache.insert_with_ttl(64, 64, ttl);
+    assert_eq!(cache.ttl_remaining(&64), Some(ttl));
+    clock.advance(ttl / 2);
+    assert!((0..64).all(|key| !cache.peek(&key).unwrap().is_expired()));
+    clock.advance(ttl / 2 + Duration::from_secs(1));
+    assert!((0..65).all(|key| cache.peek(&key).unwrap().is_expired()));
+}
+
+#[test]
+fn registered_caches_share_a_weight_budget() {
+    let registry = CacheRegistry::with_max_weight(8);
+    let users = registry.register(
+        "users",
+        Cache::<u32, String>::builder().capacity(8).ttl(TTL),
+    );
+    let counts = registry.register_typed(Cache::<u64, u64>::builder().capacity(8).ttl(TTL));
+    assert!(registry.get::<u32, String>("users").is_some());
+    // The types must match too.
+    assert!(registry.get::<u32, u32>("users").is_none());
+    assert!(Arc::ptr_eq(
+        &registry.get_typed::<u64, u64>().unwrap(),
+        &counts
+    ));
+
+    for key in 0..6 {
+        users.insert(key, key.to_string());
+    }
+    for key in 0..6 {
+        counts.insert(key, key);
+    }
+    assert_eq!(registry.weight(), 8);
+    assert_eq!(registry.len(), 8);
+    // The oldest entries of all the caches are evicted first.
+    assert!(!users.contains_key(&0) && !users.contains_key(&3));
+    assert!(users.contains_key(&4));
+    assert_eq!(counts.len(), 6);
+
+    let mut names = registry.names();
+    names.sort();
+    assert_eq!(names.len(), 2);
+    assert_eq!(names[1], "users");
+    #[cfg(feature = "stats")]
+    {
+        assert_eq!(registry.stats().insertions, 12);
+        assert_eq!(registry.stats_by_cache().len(), 2);
+    }
+    assert!(registry.unregister("users"));
+    assert!(registry.get::<u32, String>("users").is_none());
+    assert_eq!(registry.len(), 6);
+}
+
+#[test]
+fn pooled_caches_evict_the_oldest_entries_of_the_pool() {
+    let clock = MockClock::new();
+    let pool = CapacityPool::new(10);
+    let evicted = Arc::new(AtomicUsize::new(0));
+    let counter = evicted.clone();
+    let a = pool.build(
+        Cache::<u32, u32>::builder()
+            .capacity(8)
+            .ttl(TTL)
+            .weigher(|_, v| *v)
+            .eviction_listener(move |_, _, cause| {
+                assert_eq!(cause, RemovalCause::Size);
+                counter.fetch_add(1, Ordering::Relaxed);
+            })
+            .clock(clock.clone()),
+    );
+    let b = pool.build(
+        Cache::<u32, u32>::builder()
+            .capacity(8)
+            .ttl(TTL)
+            .weigher(|_, v| *v)
+            .clock(clock.clone()),
+    );
+    a.insert(1, 4);
+    clock.advance(Duration::from_secs(1));
+    b.insert(1, 4);
+    clock.advance(Duration::from_secs(1));
+    a.insert(2, 1);
+    assert_eq!(pool.weight(), 9);
+
+    // The write to `b` makes room in `a`, which holds the oldest entry.
+    clock.advance(Duration::from_secs(1));
+    b.insert(2, 3);
+    assert_eq!(evicted.load(Ordering::Relaxed), 1);
+    assert!(!a.contains_key(&1) && a.contains_key(&2));
+    assert_eq!(b.len(), 2);
+    assert_eq!(pool.weight(), 8);
+
+    // An entry heavier than the whole pool is never inserted.
+    b.insert(3, 11);
+    assert!(!b.contains_key(&3));
+    assert_eq!(pool.weight(), 8);
+}
+
+#[test]
+fn get_with_projects_values_which_cannot_be_cloned() {
+    struct Large {
+        id: u32,
+        payload: Vec<u8>,
+    }
+
+    let clock = MockClock::new();
+    let cache = Cache::<u32, Large>::builder()
+        .capacity(8)
+        .ttl(TTL)
+        .stale_ttl(TTL)
+        .clock(clock.clone())
+        .build();
+    let payload = vec![0; 1024];
+    cache.insert(1, Large { id: 7, payload });
+    clock.advance(TTL / 4);
+    let (id, meta) = cache.get_with(&1, |v| v.id).unwrap();
+    assert_eq!(id, 7);
+    assert!(!meta.is_expired());
+    assert_eq!(meta.age(), TTL / 4);
+    assert_eq!(meta.remaining(), TTL * 3 / 4);
+    assert_eq!(cache.get_with(&1, |v| v.payload.len()).unwrap().0, 1024);
+    assert!(cache.get_with(&2, |v| v.id).is_none());
+
+    clock.advance(TTL);
+    let (_, meta) = cache.get_with(&1, |v| v.id).unwrap();
+    assert!(meta.is_expired());
+    assert_eq!(meta.remaining(), Duration::ZERO);
+}
+
+#[test]
+fn len_counts_entries_rather_than_queue_slots() {
+    let clock = MockClock::new();
+    let cache = Arc::new(
+        Cache::<u32, u32>::builder()
+            .capacity(1024)
+            .ttl(TTL)
+            .clock(clock.clone())
+            .build(),
+    );
+    cache.insert(1, 1);
+    cache.insert(2, 2);
+    cache.insert(1, 10);
+    assert_eq!(cache.remove(&2), Some(2));
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.queue_len(), 2);
+
+    let writers: Vec<_> = (0..4)
+        .map(|t| {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                for key in (t * 100..t * 100 + 100).map(|k| k + 1000) {
+                    cache.insert(key, key);
+                    if key % 2 == 0 {
+                        cache.remove(&key);
+                    }
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    assert_eq!(cache.len(), 201);
+    assert_eq!(cache.len(), cache.iter().count());
+
+    // Expired entries count until they're removed.
+    clock.advance(TTL * 2);
+    assert_eq!(cache.len(), 201);
+    cache.evict_expired();
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn expired_entries_can_be_drained_from_the_head_of_the_queue() {
+    let clock = MockClock::new();
+    let cache = Cache::<u32, u32>::builder()
+        .capacity(16)
+        .ttl(TTL)
+        .clock(clock.clone())
+        .build();
+    cache.insert(1, 1);
+    cache.insert(2, 2);
+    clock.advance(TTL / 2);
+    cache.insert(3, 3);
+    clock.advance(TTL / 2 + Duration::from_millis(1));
+
+    // The drain stops at the first entry which is still alive.
+    assert_eq!(cache.drain_expired().collect::<Vec<_>>(), [(1, 1), (2, 2)]);
+    assert_eq!(cache.drain_expired().count(), 0);
+    assert_eq!(cache.len(), 1);
+
+    cache.insert(4, 4);
+    let (key, value) = cache.pop_oldest().unwrap();
+    assert_eq!(key, 3);
+    assert!(!value.is_expired());
+    assert_eq!(value.into_inner(), 3);
+    clock.advance(TTL * 2);
+    let (key, value) = cache.pop_oldest().unwrap();
+    assert_eq!(key, 4);
+    assert!(value.is_expired());
+    assert!(cache.pop_oldest().is_none());
+    assert!(cache.is_empty());
+}
+
+/// A subscriber which records the names of the spans and the messages of the events.
+#[cfg(feature = "tracing")]
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+#[cfg(feature = "tracing")]
+impl tracing::Subscriber for Recorder {
+    fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+        let mut log = self.0.lock().unwrap();
+        log.push(span.metadata().name().to_owned());
+        tracing::span::Id::from_u64(log.len() as u64)
+    }
+
+    fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+    fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+    fn event(&self, event: &tracing::Event<'_>) {
+        struct Message(String);
+        impl tracing::field::Visit for Message {
+            fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+                if field.name() == "message" {
+                    self.0 = format!("{value:?}");
+                }
+            }
+        }
+        let mut message = Message(String::new());
+        event.record(&mut message);
+        let level = event.metadata().level();
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("{level} {}", message.0));
+    }
+
+    fn enter(&self, _: &tracing::span::Id) {}
+
+    fn exit(&self, _: &tracing::span::Id) {}
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn sweeps_and_evictions_are_traced() {
+    let recorder = Recorder::default();
+    let clock = MockClock::new();
+    let cache = Cache::<u32, u32>::builder()
+        .capacity(2)
+        .ttl(TTL)
+        .clock(clock.clone())
+        .name("traced")
+        .slow_sweep_threshold(Duration::ZERO)
+        .build();
+    tracing::subscriber::with_default(recorder.clone(), || {
+        for key in 0..3 {
+            cache.insert(key, key);
+        }
+        clock.advance(TTL * 2);
+        cache.evict_expired();
+    });
+    let log = recorder.0.lock().unwrap();
+    for expected in [
+        "fastcache.evict",
+        "DEBUG entries evicted under pressure",
+        "TRACE entry removed",
+        "fastcache.sweep",
+        "WARN slow expiration sweep",
+    ] {
+        assert!(log.iter().any(|l| l == expected), "{expected:?} in {log:?}");
+    }
+}
+
+/// Hashes every key to the same value.
+#[derive(Clone, Default)]
+struct Colliding;
+
+impl std::hash::BuildHasher for Colliding {
+    type Hasher = Colliding;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        Colliding
+    }
+}
+
+impl std::hash::Hasher for Colliding {
+    fn finish(&self) -> u64 {
+        42
+    }
+
+    fn write(&mut self, _: &[u8]) {}
+}
+
+#[test]
+fn keys_are_stored_once_and_slots_tell_colliding_keys_apart() {
+    let cache = Cache::<Arc<String>, u32>::builder()
+        .capacity(2)
+        .ttl(TTL)
+        .build();
+    let key = Arc::new("key".to_owned());
+    cache.insert(key.clone(), 1);
+    cache.insert(key.clone(), 2);
+    // One reference is held by the map, and none by the queue.
+    assert_eq!(Arc::strong_count(&key), 2);
+
+    let evicted = Arc::new(Mutex::new(Vec::new()));
+    let cache = Cache::<String, u32>::builder()
+        .capacity(2)
+        .ttl(TTL)
+        .hasher(Colliding)
+        .eviction_listener({
+            let evicted = evicted.clone();
+            move |k: &String, _: &u32, cause| evicted.lock().unwrap().push((k.clone(), cause))
+        })
+        .build();
+    cache.insert("a".to_owned(), 1);
+    cache.insert("b".to_owned(), 2);
+    cache.insert("a".to_owned(), 10);
+    cache.insert("c".to_owned(), 3);
+    assert!(cache.get("a").is_none());
+    assert_eq!(*cache.get("b").unwrap(), 2);
+    assert_eq!(*cache.get("c").unwrap(), 3);
+    assert_eq!(cache.remove("b"), Some(2));
+    cache.insert("d".to_owned(), 4);
+    cache.insert("e".to_owned(), 5);
+    assert!(cache.get("c").is_none());
+    assert_eq!(*cache.get("d").unwrap(), 4);
+    assert_eq!(*cache.get("e").unwrap(), 5);
+    assert_eq!(
+        *evicted.lock().unwrap(),
+        [
+            ("a".to_owned(), RemovalCause::Replaced),
+            ("a".to_owned(), RemovalCause::Size),
+            ("b".to_owned(), RemovalCause::Explicit),
+            ("c".to_owned(), RemovalCause::Size),
+        ]
+    );
+}
+
+#[test]
+fn a_huge_capacity_is_allocated_as_the_cache_grows() {
+    let cache = Cache::<u32, u32>::new(50_000_000, TTL);
+    let empty = cache.estimated_memory_usage();
+    assert!(empty < 16 << 20, "{empty} bytes allocated up front");
+
+    let n = 200_000;
+    for key in 0..n {
+        cache.insert(key, key);
+    }
+    assert_eq!(cache.len(), n as usize);
+    assert!((0..n).all(|key| cache.get(&key).is_some_and(|v| *v == key)));
+    assert!(cache.estimated_memory_usage() > empty);
+}
+
+#[test]
+fn hash_seeds_make_the_shards_of_keys_reproducible() {
+    let sharded = |seed| {
+        Cache::<u32, u32>::builder()
+            .capacity(64)
+            .ttl(TTL)
+            .hash_seeds(seed, 2, 3, 4)
+            .build_sharded(16)
+    };
+    let placement = |cache: &ShardedCache<u32, u32>| -> Vec<usize> {
+        (0..64)
+            .map(|key| {
+                let shard = cache.shard(&key);
+                cache
+                    .shards()
+                    .iter()
+                    .position(|s| Arc::ptr_eq(s, shard))
+                    .unwrap()
+            })
+            .collect()
+    };
+    assert_eq!(placement(&sharded(1)), placement(&sharded(1)));
+    assert_ne!(placement(&sharded(1)), placement(&sharded(5)));
+
+    let cache = Cache::<u32, u32, RandomState>::with_hasher(8, TTL, RandomState::new());
+    cache.insert(1, 1);
+    assert_eq!(*cache.get(&1).unwrap(), 1);
+    let cache = Cache::<u32, u32, Colliding>::with_hasher(8, TTL, Colliding);
+    for key in 0..8 {
+        cache.insert(key, key);
+    }
+    assert!((0..8).all(|key| cache.get(&key).is_some_and(|v| *v == key)));
+}
+
+#[test]
+fn invalidate_before_hides_older_entries_lazily() {
+    let removed = Arc::new(Mutex::new(Vec::new()));
+    let cache = Cache::<u32, u32>::builder()
+        .capacity(8)
+        .ttl(TTL)
+        .clock(MockClock::new())
+        .eviction_listener({
+            let removed = removed.clone();
+            move |k: &u32, _: &u32, cause| removed.lock().unwrap().push((*k, cause))
+        })
+        .build();
+    assert_eq!(cache.epoch(), 0);
+    cache.insert(1, 1);
+    cache.insert(2, 2);
+    assert!(cache.invalidate_before(3));
+    assert_eq!(cache.epoch(), 3);
+    // Applying the same or an older epoch again does nothing.
+    assert!(!cache.invalidate_before(3));
+    assert!(!cache.invalidate_before(1));
+    assert_eq!(cache.epoch(), 3);
+
+    assert!(cache.peek(&1).is_none());
+    assert!(!cache.contains_key(&2));
+    assert_eq!(cache.keys().count(), 0);
+    assert_eq!(cache.len(), 2);
+    assert!(removed.lock().unwrap().is_empty());
+
+    // Writes remove them from the head of the queue.
+    cache.insert(3, 3);
+    assert_eq!(*cache.get(&3).unwrap(), 3);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(
+        *removed.lock().unwrap(),
+        [(1, RemovalCause::Explicit), (2, RemovalCause::Explicit)]
+    );
+}
@@ -0,0 +1,153 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use fastcache::{Cache, CacheBuilder, CacheConfigError, RemovalCause, ShardedCache, SizedCache};
+
+const TTL: Duration = Duration::from_secs(60);
+
+#[test]
+fn missing_settings_are_errors() {
+    let err = CacheBuilder::<u32, u32>::new().ttl(TTL).try_build().err();
+    assert_eq!(err, Some(CacheConfigError::MissingCapacity));
+    let err = CacheBuilder::<u32, u32>::new()
+        .capacity(1)
+        .try_build()
+        .err();
+    assert_eq!(err, Some(CacheConfigError::MissingTtl));
+    let err = ShardedCache::<u32, u32>::try_new(8, TTL, 0).err();
+    assert_eq!(err, Some(CacheConfigError::ZeroShards));
+}
+
+#[test]
+fn too_large_settings_are_errors() {
+    let err = Cache::<u32, u32>::try_new(usize::MAX, TTL).err();
+    assert_eq!(err, Some(CacheConfigError::CapacityTooLarge));
+    // Durations saturate instead.
+    let half = Duration::from_secs(u64::MAX / 4);
+    assert!(Cache::<u32, u32>::try_new(1, Duration::MAX).is_ok());
+    assert!(Cache::<u32, u32>::builder()
+        .capacity(1)
+        .ttl(half)
+        .stale_ttl(half)
+        .time_to_idle(Duration::MAX)
+        .try_build()
+        .is_ok());
+}
+
+#[test]
+fn zero_capacity_is_pass_through() {
+    let rejected = Arc::new(AtomicUsize::new(0));
+    let counter = rejected.clone();
+    let cache = Cache::<u32, u32>::builder()
+        .capacity(0)
+        .ttl(TTL)
+        .eviction_listener(move |_, _, cause| {
+            assert_eq!(cause, RemovalCause::Size);
+            counter.fetch_add(1, Ordering::Relaxed);
+        })
+        .try_build()
+        .unwrap();
+    assert!(cache.is_disabled());
+
+    cache.insert(1, 1);
+    assert!(cache.get(&1).is_none());
+    assert!(cache.try_insert(2, 2).is_err());
+    assert_eq!(*cache.get_or_insert_with(3, || 3), 3);
+    assert_eq!(*cache.entry(4).or_insert(4), 4);
+    assert!(cache.is_empty());
+    assert_eq!(rejected.load(Ordering::Relaxed), 3);
+
+    let sized = SizedCache::new(0);
+    sized.insert(1, 1);
+    assert!(sized.get(&1).is_none());
+}
+
+#[test]
+fn zero_ttl_is_pass_through() {
+    let cache = Cache::<u32, u32>::new(8, Duration::ZERO);
+    assert!(cache.is_disabled());
+
+    cache.insert(1, 1);
+    cache.insert_with_ttl(2, 2, TTL);
+    assert!(cache.get(&1).is_none());
+    assert!(cache.get(&2).is_none());
+
+    let calls = AtomicUsize::new(0);
+    for _ in 0..2 {
+        let value = cache.get_or_insert_with(3, || calls.fetch_add(1, Ordering::Relaxed) as u32);
+        assert_eq!(value.version(), 0);
+    }
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn custom_hasher_and_shard_amount() {
+    let cache = Cache::<u32, u32>::builder()
+        .capacity(8)
+        .ttl(TTL)
+        .hasher(std::collections::hash_map::RandomState::new())
+        .shard_amount(4)
+        .build();
+    cache.insert(1, 1);
+    assert_eq!(*cache.get(&1).unwrap(), 1);
+
+    for shard_amount in [0, 1, 3] {
+        let err = Cache::<u32, u32>::builder()
+            .capacity(8)
+            .ttl(TTL)
+            .shard_amount(shard_amount)
+            .try_build()
+            .err();
+        assert_eq!(err, Some(CacheConfigError::InvalidShardAmount));
+    }
+}
+
+#[test]
+fn unsupported_options_are_errors_for_local_caches() {
+    let builder = || CacheBuilder::<u32, u32>::new().capacity(8).ttl(TTL);
+    assert!(builder()
+        .hash_seeds(1, 2, 3, 4)
+        .eviction_listener(|_: &u32, _: &u32, _| {})
+        .try_build_local()
+        .is_ok());
+    let err = builder().weigher(|_, _| 2).try_build_local().err();
+    assert_eq!(err, Some(CacheConfigError::UnsupportedOption("weigher")));
+    let err = builder().time_to_idle(TTL).try_build_local().err();
+    assert_eq!(
+        err,
+        Some(CacheConfigError::UnsupportedOption("time_to_idle"))
+    );
+    let err = builder().spawner(Err).try_build_local().err();
+    assert_eq!(err, Some(CacheConfigError::UnsupportedOption("spawner")));
+    assert_eq!(
+        err.unwrap().to_string(),
+        "cache option `spawner` isn't supported by this cache"
+    );
+    let err = CacheBuilder::<u32, u32>::new()
+        .ttl(TTL)
+        .try_build_local()
+        .err();
+    assert_eq!(err, Some(CacheConfigError::MissingCapacity));
+    #[cfg(feature = "read-mostly")]
+    {
+        let err = builder().max_weight(4).try_build_read_mostly().err();
+        assert_eq!(err, Some(CacheConfigError::UnsupportedOption("max_weight")));
+        assert!(builder().try_build_read_mostly().is_ok());
+    }
+}
+
+#[test]
+#[should_panic(expected = "cache option `stale_ttl` isn't supported by this cache")]
+fn build_local_panics_on_unsupported_options() {
+    CacheBuilder::<u32, u32>::new()
+        .capacity(8)
+        .ttl(TTL)
+        .stale_ttl(TTL)
+        .build_local();
+}
@@ -0,0 +1,43 @@
+#![cfg(feature = "serde")]
+
+use std::time::Duration;
+
+use fastcache::{Cache, ShardedCache};
+
+const TTL: Duration = Duration::from_secs(60);
+
+#[test]
+fn dump_and_restore() {
+    let cache = Cache::<u32, String>::new(8, TTL);
+    cache.insert(1, "one".to_owned());
+    cache.insert(2, "two".to_owned());
+    let dump = cache.dump(serde_json::value::Serializer).unwrap();
+
+    let restored = Cache::<u32, String>::new(8, TTL);
+    restored.restore(dump).unwrap();
+    assert_eq!(restored.get(&1).unwrap().as_str(), "one");
+    assert_eq!(restored.get(&2).unwrap().as_str(), "two");
+}
+
+#[test]
+fn dump_and_restore_sharded() {
+    let cache = ShardedCache::<u32, u32>::new(64, TTL, 4);
+    cache.insert_many((0..8).map(|i| (i, i)));
+    let dump = cache.dump(serde_json::value::Serializer).unwrap();
+
+    let restored = ShardedCache::<u32, u32>::new(64, TTL, 4);
+    restored.restore(dump).unwrap();
+    assert_eq!(restored.len(), 8);
+    assert!((0..8).all(|i| restored.get(&i).is_some_and(|v| *v == i)));
+}
+
+#[test]
+fn corrupted_ttl_is_an_error() {
+    let dump = serde_json::json!([
+        { "key": 1, "value": 1, "ttl": { "secs": 1, "nanos": 0 } },
+        { "key": 2, "value": 2, "ttl": { "secs": u64::MAX, "nanos": 0 } },
+    ]);
+    let cache = Cache::<u32, u32>::new(8, TTL);
+    assert!(cache.restore(dump).is_err());
+    assert!(cache.is_empty());
+}